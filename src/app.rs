@@ -0,0 +1,96 @@
+use winit::event::Event;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::WindowId;
+
+use crate::core::{MamgaGfx, Renderer};
+use crate::error::MagmaError;
+use crate::input::Input;
+use crate::timing::FrameContext;
+
+/// Application driven by `MamgaGfx::run_app`, as an alternative to passing a
+/// single closure to `MamgaGfx::run`.
+///
+/// `T` is the custom event type sent through the window's `EventLoopProxy`.
+pub trait App<T: 'static = ()> {
+    /// Called once after the device exists and before the first frame, which
+    /// makes it the place to load textures.
+    fn init(&mut self, _gfx: &mut MamgaGfx) -> Result<(), MagmaError> {
+        Ok(())
+    }
+
+    fn update(&mut self, _frame: &FrameContext, _input: &Input) -> Result<(), MagmaError> {
+        Ok(())
+    }
+
+    fn draw(&mut self, renderer: &mut Renderer, frame: &FrameContext) -> Result<(), MagmaError>;
+
+    /// Runs one frame. Defaults to `update` followed by `draw`.
+    fn frame(&mut self, renderer: &mut Renderer, frame: &FrameContext, input: &Input) -> Result<(), MagmaError> {
+        self.update(frame, input)?;
+        self.draw(renderer, frame)
+    }
+
+    /// Sees every winit event, including custom user events, before magma-gfx
+    /// handles it.
+    fn on_event(&mut self, _event: &Event<T>) {}
+
+    /// Called once per loop iteration, after the events and before the
+    /// frame, with the running event loop. This is where windows can be
+    /// opened with `MamgaGfx::create_window` or closed with `close_window`.
+    fn manage_windows(
+        &mut self,
+        _gfx: &mut MamgaGfx,
+        _target: &EventLoopWindowTarget<T>,
+    ) -> Result<(), MagmaError> {
+        Ok(())
+    }
+
+    /// Draws a window opened with `MamgaGfx::create_window`, after the main
+    /// window's `frame`. `input` only holds what happened in that window.
+    fn draw_window(
+        &mut self,
+        _id: WindowId,
+        _renderer: &mut Renderer,
+        _frame: &FrameContext,
+        _input: &Input,
+    ) -> Result<(), MagmaError> {
+        Ok(())
+    }
+
+    /// Called with the main window's new framebuffer size in pixels.
+    fn on_resize(&mut self, _size: [u32; 2]) {}
+
+    /// Called after an extra window was closed by its close button.
+    fn on_window_closed(&mut self, _id: WindowId) {}
+
+    /// Called once when the event loop shuts down.
+    fn on_exit(&mut self) {}
+}
+
+/// Adapts the closure given to `MamgaGfx::run` to the `App` trait.
+pub(crate) struct ClosureApp<F>(pub F);
+
+impl<T: 'static, F> App<T> for ClosureApp<F>
+where
+    F: FnMut(&mut Renderer, &FrameContext, &Input) -> Result<(), MagmaError>,
+{
+    fn draw(&mut self, _renderer: &mut Renderer, _frame: &FrameContext) -> Result<(), MagmaError> {
+        Ok(())
+    }
+
+    fn frame(&mut self, renderer: &mut Renderer, frame: &FrameContext, input: &Input) -> Result<(), MagmaError> {
+        (self.0)(renderer, frame, input)
+    }
+
+    /// Extra windows get the same closure, which can tell them apart with
+    /// `Renderer::window_id`.
+    fn draw_window(
+        &mut self,
+        _id: WindowId,
+        renderer: &mut Renderer,
+        frame: &FrameContext,
+        input: &Input,
+    ) -> Result<(), MagmaError> {
+        (self.0)(renderer, frame, input)
+    }
+}
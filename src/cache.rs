@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::instance::PhysicalDevice;
+use vulkano::pipeline::cache::PipelineCache;
+
+use crate::error::MagmaError;
+
+/// Length of the header every implementation puts at the start of pipeline
+/// cache data (`VkPipelineCacheHeaderVersionOne`).
+const HEADER_LEN: usize = 32;
+const HEADER_VERSION_ONE: u32 = 1;
+
+/// Finishes a `GraphicsPipelineBuilder` with the renderer's pipeline cache,
+/// so the driver reuses compiled shaders and the saved cache file fills up.
+macro_rules! build_pipeline {
+    ($builder:expr, $device:expr, $cache:expr) => {{
+        let cache: &std::sync::Arc<vulkano::pipeline::cache::PipelineCache> = &$cache;
+        $builder.build_with_cache(cache.clone()).build($device.clone())
+    }};
+}
+pub(crate) use build_pipeline;
+
+/// Creates the device's pipeline cache, seeded from `path` when it holds
+/// data saved for this same device and driver. Anything else is ignored and
+/// the cache starts out empty.
+pub(crate) fn load_pipeline_cache(
+    device: &Arc<Device>,
+    path: Option<&Path>,
+) -> Result<Arc<PipelineCache>, MagmaError> {
+    if let Some(path) = path {
+        match fs::read(path) {
+            Ok(data) if is_compatible(device.physical_device(), &data) => {
+                // Safe because the header matches this device, so the driver
+                // validates the rest.
+                return Ok(unsafe { PipelineCache::with_data(device.clone(), &data)? });
+            }
+            Ok(_) => {
+                log::info!("ignoring pipeline cache {} saved for another device or driver", path.display());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                log::warn!("couldn't read pipeline cache {}: {}", path.display(), e);
+            }
+        }
+    }
+    Ok(PipelineCache::empty(device.clone())?)
+}
+
+/// Writes the cache's data to `path`, through a temporary file so a crash
+/// can't leave a truncated cache behind.
+pub(crate) fn save_pipeline_cache(cache: &PipelineCache, path: &Path) -> Result<(), MagmaError> {
+    let data = cache.get_data()?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Checks the header of saved cache data against `physical`, so data from
+/// another GPU or driver version is never handed to the driver.
+fn is_compatible(physical: PhysicalDevice, data: &[u8]) -> bool {
+    header_matches(data, physical.pci_vendor_id(), physical.pci_device_id(), physical.uuid())
+}
+
+/// Whether `data` starts with a version one header for the given vendor,
+/// device and pipeline cache UUID.
+fn header_matches(data: &[u8], vendor_id: u32, device_id: u32, uuid: &[u8]) -> bool {
+    if data.len() < HEADER_LEN {
+        return false;
+    }
+    let word = |i: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[i * 4 .. i * 4 + 4]);
+        // The header is little-endian whatever the host is.
+        u32::from_le_bytes(bytes)
+    };
+    let header_len = word(0) as usize;
+    header_len >= HEADER_LEN
+        && header_len <= data.len()
+        && word(1) == HEADER_VERSION_ONE
+        && word(2) == vendor_id
+        && word(3) == device_id
+        && data[16 .. 32] == uuid[..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VENDOR: u32 = 0x10de;
+    const DEVICE: u32 = 0x1c82;
+    const UUID: [u8; 16] = [7; 16];
+
+    fn header(header_len: u32, version: u32, vendor_id: u32, device_id: u32, uuid: [u8; 16]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [header_len, version, vendor_id, device_id].iter() {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&uuid);
+        // Driver data follows the header.
+        data.extend_from_slice(&[0xab; 8]);
+        data
+    }
+
+    fn matches(data: &[u8]) -> bool {
+        header_matches(data, VENDOR, DEVICE, &UUID)
+    }
+
+    #[test]
+    fn valid_header_matches() {
+        assert!(matches(&header(32, HEADER_VERSION_ONE, VENDOR, DEVICE, UUID)));
+    }
+
+    #[test]
+    fn short_data_is_rejected() {
+        let data = header(32, HEADER_VERSION_ONE, VENDOR, DEVICE, UUID);
+        assert!(!matches(&data[.. HEADER_LEN - 1]));
+        assert!(!matches(&[]));
+    }
+
+    #[test]
+    fn header_len_outside_the_data_is_rejected() {
+        assert!(!matches(&header(41, HEADER_VERSION_ONE, VENDOR, DEVICE, UUID)));
+        assert!(!matches(&header(16, HEADER_VERSION_ONE, VENDOR, DEVICE, UUID)));
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        assert!(!matches(&header(32, 2, VENDOR, DEVICE, UUID)));
+    }
+
+    #[test]
+    fn other_vendor_or_device_is_rejected() {
+        assert!(!matches(&header(32, HEADER_VERSION_ONE, 0x1002, DEVICE, UUID)));
+        assert!(!matches(&header(32, HEADER_VERSION_ONE, VENDOR, 0x1c83, UUID)));
+    }
+
+    #[test]
+    fn other_uuid_is_rejected() {
+        let mut uuid = UUID;
+        uuid[15] = 8;
+        assert!(!matches(&header(32, HEADER_VERSION_ONE, VENDOR, DEVICE, uuid)));
+    }
+}
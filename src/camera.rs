@@ -20,6 +20,12 @@ pub fn ortho_matrix_vulk(left: f32, right: f32 , bottom: f32, top: f32, z_near:
 }
 
 pub fn get_projection_matrix(size: Vec2, position: Vec2, dimensions: [f32; 2]) -> Mat4 {
+    get_projection_matrix_z(size, position, 0.0, dimensions)
+}
+
+/// Like `get_projection_matrix`, but places the quad at depth `z` in
+/// `-1.0..=1.0`, where higher values end up in front.
+pub fn get_projection_matrix_z(size: Vec2, position: Vec2, z: f32, dimensions: [f32; 2]) -> Mat4 {
     let proj = ortho_matrix_vulk(0.0, *dimensions.get(0).unwrap(), *dimensions.get(1).unwrap(), 0.0, -1.0, 1.0);
     let camera_pos = vec3(0.0, 0.0, 3.0);
     let camera_front = vec3(0.0, 0.0, -1.0);
@@ -28,7 +34,7 @@ pub fn get_projection_matrix(size: Vec2, position: Vec2, dimensions: [f32; 2]) -
         camera_pos + camera_front, // and looks at the origin
         Vec3::new(0.0,-1.0,0.0)  // Head is up (set to 0,-1,0 to look upside-down)
     );
-    let model = Mat4::from_scale_rotation_translation(Vec3::new(size.x(), size.y(), 1.0), Quat::identity(), Vec3::new(position.x(), position.y(), 1.0));
+    let model = Mat4::from_scale_rotation_translation(Vec3::new(size.x(), size.y(), 1.0), Quat::identity(), Vec3::new(position.x(), position.y(), z));
 
     let mut correction = Mat4::identity();
     
@@ -0,0 +1,144 @@
+use vulkano::pipeline::viewport::{Scissor, Viewport};
+
+use crate::core::Renderer;
+use crate::error::MagmaError;
+use crate::shapes::Rectangle;
+
+impl Renderer {
+    /// Restricts every following draw to `rectangle`, given in draw
+    /// coordinates like any other `Rectangle`. Nested clips intersect with
+    /// the ones pushed before them.
+    pub fn push_clip(&mut self, rectangle: &Rectangle) -> Result<(), MagmaError> {
+        let viewport = self.dynamic_state.viewports.as_ref().and_then(|viewports| viewports.get(0)).ok_or_else(|| {
+            MagmaError::Pipeline("push_clip needs a viewport to map the rectangle through".to_string())
+        })?;
+        let projection = self.projection_dimensions();
+        let center = [rectangle.position.x(), rectangle.position.y()];
+        let size = [rectangle.width as f32, rectangle.height as f32];
+        let scissor = clip_scissor(viewport, projection, center, size, &self.current_clip());
+        self.clip_stack.push(scissor);
+        self.apply_clip();
+        Ok(())
+    }
+
+    /// Goes back to the clip in place before the last `push_clip`.
+    pub fn pop_clip(&mut self) -> Result<(), MagmaError> {
+        self.clip_stack.pop().ok_or_else(|| {
+            MagmaError::Pipeline("pop_clip called without a pushed clip".to_string())
+        })?;
+        self.apply_clip();
+        Ok(())
+    }
+
+    /// The scissor draws are clipped to, in framebuffer pixels.
+    pub fn current_clip(&self) -> Scissor {
+        top_clip(&self.clip_stack)
+    }
+
+    pub(crate) fn apply_clip(&mut self) {
+        self.dynamic_state.scissors = Some(vec![self.current_clip()]);
+    }
+}
+
+/// The clip on top of `stack`, or the whole framebuffer when it's empty.
+fn top_clip(stack: &[Scissor]) -> Scissor {
+    stack.last().cloned().unwrap_or_else(Scissor::irrelevant)
+}
+
+/// Maps a rectangle centered on `center` in draw coordinates through
+/// `viewport` to framebuffer pixels and intersects it with `current`.
+fn clip_scissor(viewport: &Viewport, projection: [f32; 2], center: [f32; 2], size: [f32; 2], current: &Scissor) -> Scissor {
+    let scale = [viewport.dimensions[0] / projection[0], viewport.dimensions[1] / projection[1]];
+    let mut min = [0i64; 2];
+    let mut max = [0i64; 2];
+    for axis in 0 .. 2 {
+        let half = size[axis] / 2.0;
+        // Round outwards so edges of the rectangle are never clipped off.
+        min[axis] = (viewport.origin[axis] + (center[axis] - half) * scale[axis]).floor() as i64;
+        max[axis] = (viewport.origin[axis] + (center[axis] + half) * scale[axis]).ceil() as i64;
+
+        let current_min = current.origin[axis] as i64;
+        let current_max = current_min + current.dimensions[axis] as i64;
+        // Scissors can't start left of or above the framebuffer.
+        min[axis] = min[axis].max(current_min).max(0);
+        max[axis] = max[axis].min(current_max).max(min[axis]);
+    }
+
+    Scissor {
+        origin: [min[0] as i32, min[1] as i32],
+        dimensions: [(max[0] - min[0]) as u32, (max[1] - min[1]) as u32],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(origin: [f32; 2], dimensions: [f32; 2]) -> Viewport {
+        Viewport { origin, dimensions, depth_range: 0.0 .. 1.0 }
+    }
+
+    fn assert_scissor(scissor: &Scissor, origin: [i32; 2], dimensions: [u32; 2]) {
+        assert_eq!((scissor.origin, scissor.dimensions), (origin, dimensions));
+    }
+
+    #[test]
+    fn edges_round_outwards() {
+        // 1.5 framebuffer pixels per unit puts both edges mid-pixel.
+        let viewport = viewport([0.0, 0.0], [150.0, 150.0]);
+        let scissor = clip_scissor(&viewport, [100.0, 100.0], [10.0, 10.0], [5.0, 5.0], &Scissor::irrelevant());
+        // 11.25..18.75 widens to 11..19.
+        assert_scissor(&scissor, [11, 11], [8, 8]);
+    }
+
+    #[test]
+    fn viewport_origin_offsets_the_clip() {
+        let viewport = viewport([20.0, 30.0], [100.0, 100.0]);
+        let scissor = clip_scissor(&viewport, [100.0, 100.0], [50.0, 50.0], [20.0, 10.0], &Scissor::irrelevant());
+        assert_scissor(&scissor, [60, 75], [20, 10]);
+    }
+
+    #[test]
+    fn nested_clips_intersect() {
+        let viewport = viewport([0.0, 0.0], [100.0, 100.0]);
+        let outer = clip_scissor(&viewport, [100.0, 100.0], [30.0, 30.0], [40.0, 40.0], &Scissor::irrelevant());
+        assert_scissor(&outer, [10, 10], [40, 40]);
+        let inner = clip_scissor(&viewport, [100.0, 100.0], [50.0, 20.0], [40.0, 20.0], &outer);
+        assert_scissor(&inner, [30, 10], [20, 20]);
+    }
+
+    #[test]
+    fn disjoint_clips_are_empty() {
+        let viewport = viewport([0.0, 0.0], [100.0, 100.0]);
+        let outer = clip_scissor(&viewport, [100.0, 100.0], [10.0, 10.0], [20.0, 20.0], &Scissor::irrelevant());
+        let inner = clip_scissor(&viewport, [100.0, 100.0], [80.0, 80.0], [20.0, 20.0], &outer);
+        assert_eq!(inner.dimensions, [0, 0]);
+    }
+
+    #[test]
+    fn negative_origin_is_clamped_to_the_framebuffer() {
+        let viewport = viewport([0.0, 0.0], [100.0, 100.0]);
+        let scissor = clip_scissor(&viewport, [100.0, 100.0], [0.0, -5.0], [20.0, 20.0], &Scissor::irrelevant());
+        assert_scissor(&scissor, [0, 0], [10, 5]);
+
+        // A letterboxed viewport can start below zero too.
+        let viewport = viewport([-40.0, 0.0], [200.0, 100.0]);
+        let scissor = clip_scissor(&viewport, [100.0, 100.0], [10.0, 50.0], [20.0, 20.0], &Scissor::irrelevant());
+        assert_scissor(&scissor, [0, 40], [0, 20]);
+    }
+
+    #[test]
+    fn popping_every_clip_restores_the_full_scissor() {
+        let viewport = viewport([0.0, 0.0], [100.0, 100.0]);
+        let mut stack = Vec::new();
+        stack.push(clip_scissor(&viewport, [100.0, 100.0], [30.0, 30.0], [40.0, 40.0], &top_clip(&stack)));
+        stack.push(clip_scissor(&viewport, [100.0, 100.0], [40.0, 40.0], [10.0, 10.0], &top_clip(&stack)));
+        assert_scissor(&top_clip(&stack), [35, 35], [10, 10]);
+
+        stack.pop();
+        assert_scissor(&top_clip(&stack), [10, 10], [40, 40]);
+        stack.pop();
+        let full = Scissor::irrelevant();
+        assert_scissor(&top_clip(&stack), full.origin, full.dimensions);
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use vulkano::format::Format;
+use vulkano::swapchain::PresentMode;
+
+use crate::core::{CoordinateMode, MamgaGfx};
+use crate::debug::DebugConfig;
+use crate::device::DeviceSelector;
+use crate::error::MagmaError;
+use crate::resolution::VirtualResolution;
+use crate::timing::{valid_fps, valid_timestep};
+use crate::{init_renderer_with, Window};
+
+/// How the window should occupy the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    Windowed,
+    /// Fullscreen window on the primary monitor without changing its video mode.
+    Borderless,
+    /// Exclusive fullscreen using the primary monitor's first video mode.
+    Fullscreen,
+}
+
+/// Window and swapchain settings used by `init_renderer_with`.
+///
+/// Options the surface can't honour fall back to a supported value, and the
+/// values actually used are stored back into `MamgaGfx::config`.
+#[derive(Debug, Clone)]
+pub struct MagmaConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub resizable: bool,
+    pub window_mode: WindowMode,
+    pub present_mode: PresentMode,
+    /// Number of swapchain images, `None` uses the surface's minimum.
+    pub image_count: Option<u32>,
+    /// Preferred surface format, `None` uses the first sRGB format the
+    /// surface lists, or its first format if none are sRGB.
+    pub surface_format: Option<Format>,
+    pub device: DeviceSelector,
+    /// Frame rate the run loop sleeps to hold, `None` renders as fast as the
+    /// present mode allows.
+    pub target_fps: Option<f64>,
+    /// Length of one fixed update, see `FrameContext::fixed_steps`.
+    pub fixed_timestep: Option<Duration>,
+    /// How many frames the CPU may record ahead of the GPU.
+    pub frames_in_flight: usize,
+    /// Adds a depth attachment so draws can be layered with a `z` value
+    /// instead of by submission order.
+    pub depth_buffer: bool,
+    /// MSAA sample count (1, 2, 4 or 8). Lowered to the highest count the
+    /// device supports.
+    pub samples: u32,
+    /// Units the renderer starts out drawing in, see `CoordinateMode`.
+    pub coordinate_mode: CoordinateMode,
+    /// Enables the validation layer and debug names, see `DebugConfig`.
+    pub debug: Option<DebugConfig>,
+    /// File the pipeline cache is loaded from at startup and saved to on
+    /// exit. Data saved by a different GPU or driver is ignored.
+    pub pipeline_cache: Option<PathBuf>,
+    /// Fixed drawing size scaled up to the window, see `VirtualResolution`.
+    pub virtual_resolution: Option<VirtualResolution>,
+}
+
+impl Default for MagmaConfig {
+    fn default() -> Self {
+        MagmaConfig {
+            title: "magma-gfx".to_string(),
+            width: 1200,
+            height: 800,
+            resizable: true,
+            window_mode: WindowMode::Windowed,
+            present_mode: PresentMode::Fifo,
+            image_count: None,
+            surface_format: None,
+            device: DeviceSelector::default(),
+            target_fps: None,
+            fixed_timestep: None,
+            frames_in_flight: 2,
+            depth_buffer: false,
+            samples: 1,
+            coordinate_mode: CoordinateMode::Physical,
+            debug: None,
+            pipeline_cache: None,
+            virtual_resolution: None,
+        }
+    }
+}
+
+impl MagmaConfig {
+    pub fn new() -> Self {
+        MagmaConfig::default()
+    }
+
+    pub fn with_title<T: Into<String>>(mut self, title: T) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn with_window_mode(mut self, window_mode: WindowMode) -> Self {
+        self.window_mode = window_mode;
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    /// Shorthand for `Fifo` when enabled and `Immediate` when disabled.
+    pub fn with_vsync(self, vsync: bool) -> Self {
+        self.with_present_mode(if vsync { PresentMode::Fifo } else { PresentMode::Immediate })
+    }
+
+    pub fn with_image_count(mut self, image_count: u32) -> Self {
+        self.image_count = Some(image_count);
+        self
+    }
+
+    pub fn with_surface_format(mut self, format: Format) -> Self {
+        self.surface_format = Some(format);
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceSelector) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Anything but a positive, finite rate leaves the frame rate uncapped.
+    pub fn with_target_fps(mut self, fps: f64) -> Self {
+        self.target_fps = Some(fps).filter(|&fps| valid_fps(fps));
+        self
+    }
+
+    /// A zero timestep turns fixed updates off.
+    pub fn with_fixed_timestep(mut self, timestep: Duration) -> Self {
+        self.fixed_timestep = Some(timestep).filter(|&step| valid_timestep(step));
+        self
+    }
+
+    pub fn with_frames_in_flight(mut self, frames: usize) -> Self {
+        self.frames_in_flight = frames.max(1);
+        self
+    }
+
+    pub fn with_depth_buffer(mut self, depth_buffer: bool) -> Self {
+        self.depth_buffer = depth_buffer;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_coordinate_mode(mut self, coordinate_mode: CoordinateMode) -> Self {
+        self.coordinate_mode = coordinate_mode;
+        self
+    }
+
+    pub fn with_virtual_resolution(mut self, resolution: VirtualResolution) -> Self {
+        self.virtual_resolution = Some(resolution);
+        self
+    }
+
+    pub fn with_pipeline_cache<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.pipeline_cache = Some(path.into());
+        self
+    }
+
+    pub fn with_debug(mut self, debug: DebugConfig) -> Self {
+        self.debug = Some(debug);
+        self
+    }
+
+    pub fn build(self) -> Result<(MamgaGfx, Window), MagmaError> {
+        init_renderer_with(self)
+    }
+}
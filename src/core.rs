@@ -2,36 +2,252 @@ use vulkano::device::Queue;
 use vulkano::device::{Device, DeviceExtensions, Features};
 use std::sync::Arc;
 use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
+use vulkano::instance::debug::DebugCallback;
 use vulkano::pipeline::GraphicsPipelineAbstract;
-use vulkano::framebuffer::RenderPassAbstract;
-use vulkano::image::SwapchainImage;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::format::Format;
+use vulkano::framebuffer::{RenderPassAbstract, Subpass};
+use vulkano::pipeline::depth_stencil::{Compare, DepthStencil};
+use vulkano::pipeline::viewport::Scissor;
 use vulkano::swapchain::{
-    ColorSpace, FullscreenExclusive, PresentMode, SurfaceTransform, Surface, Swapchain,
+    ColorSpace, FullscreenExclusive, PresentMode, SurfaceTransform, Surface,
 };
-use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState};
+use winit::window::WindowId;
 
+use crate::cache::save_pipeline_cache;
 use crate::camera::Camera;
+use crate::config::MagmaConfig;
+use crate::error::MagmaError;
+use crate::input::Input;
+use crate::timing::FrameClock;
+use crate::frames::FrameResources;
+use crate::screenshot::{CaptureTarget, PendingCapture, ScreenshotWriter};
+use crate::recorder::Recorder;
+use crate::windows::{WindowContext, WindowOutput};
+use crate::post::PostProcess;
+use crate::resolution::VirtualResolution;
+use crate::viewport::ViewportRegion;
+use crate::stats::{FrameCounter, FrameStats};
+use crate::target::{SavedTarget, TargetPass};
 
 use std::collections::HashMap;
 
+use image::RgbaImage;
+
 pub struct MamgaGfx {
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
     pub instance: Arc<Instance>,
     pub clear_color: [f32; 4],
     pub renderer: Renderer,
-    pub swapchain: Arc<Swapchain<winit::window::Window>>,
-    pub images: Vec<Arc<SwapchainImage<winit::window::Window>>>,
-    pub surface: Arc<Surface<winit::window::Window>>
+    /// Settings the window and swapchain were actually created with, after
+    /// falling back from anything the surface didn't support.
+    pub config: MagmaConfig,
+    pub input: Input,
+    /// The main window's swapchain.
+    pub(crate) output: WindowOutput,
+    pub(crate) clock: FrameClock,
+    pub(crate) screenshot_request: Option<CaptureTarget>,
+    pub(crate) pending_screenshot: Option<(PendingCapture, CaptureTarget)>,
+    pub(crate) screenshot: Option<RgbaImage>,
+    /// Started by the first screenshot saved to a file.
+    pub(crate) screenshot_writer: Option<ScreenshotWriter>,
+    pub(crate) recorder: Option<Recorder>,
+    /// Keeps validation messages flowing while set.
+    pub(crate) _debug_callback: Option<DebugCallback>,
+    /// Windows opened with `create_window`, drawn after the main one.
+    pub(crate) windows: Vec<WindowContext>,
+    /// Fullscreen passes run over the main window's frame, see `PostPass`.
+    pub post_process: PostProcess,
 }
 
 pub struct Renderer {
     pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
     pub render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     pub pipelines: HashMap<String, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
     pub command_buffer_builder: Option<AutoCommandBufferBuilder>,
     pub dynamic_state: DynamicState,
-    pub camera: Camera
+    pub camera: Camera,
+    /// One set of transient resources per frame in flight.
+    pub(crate) frames: Vec<FrameResources>,
+    /// Index into `frames` of the frame being recorded.
+    pub(crate) current_frame: usize,
+    /// Whether draw coordinates are in physical or logical pixels.
+    pub coordinate_mode: CoordinateMode,
+    pub(crate) scale_factor: f64,
+    /// Targets pushed with `push_target`, innermost last.
+    pub(crate) target_stack: Vec<SavedTarget>,
+    pub(crate) target_passes: HashMap<Format, TargetPass>,
+    /// Passes recorded into render targets this frame, in order.
+    pub(crate) target_commands: Vec<AutoCommandBuffer>,
+    /// Shared by every pipeline built for this device.
+    pub(crate) pipeline_cache: Arc<PipelineCache>,
+    /// Fixed size draw coordinates cover, see `VirtualResolution`.
+    pub(crate) virtual_resolution: Option<VirtualResolution>,
+    /// Scissors pushed with `push_clip`, innermost last.
+    pub(crate) clip_stack: Vec<Scissor>,
+    /// Part of the screen set with `set_viewport`.
+    pub(crate) viewport_region: Option<ViewportRegion>,
+    pub(crate) frame_counter: FrameCounter,
+    pub(crate) stats: FrameStats,
+    /// The render pass being drawn into has an sRGB color attachment.
+    pub(crate) output_srgb: bool,
+    /// A pipeline was built since windows last shared theirs.
+    pub(crate) pipelines_added: bool,
+    /// The window this renderer draws into, if any.
+    pub(crate) window_id: Option<WindowId>,
+    /// Size in pixels of what's being drawn into, which the viewport may
+    /// only cover part of.
+    pub(crate) framebuffer_size: [u32; 2],
+    /// Frames recorded so far, to tell one frame from the next.
+    pub(crate) frame_index: u64,
+}
+
+
+impl Renderer {
+    pub(crate) fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        dynamic_state: DynamicState,
+        frames_in_flight: usize,
+        pipeline_cache: Arc<PipelineCache>,
+    ) -> Self {
+        let frames = (0 .. frames_in_flight.max(1))
+            .map(|_| FrameResources::new(device.clone(), &queue))
+            .collect();
+        let framebuffer_size = match dynamic_state.viewports.as_ref().and_then(|viewports| viewports.get(0)) {
+            Some(viewport) => [viewport.dimensions[0] as u32, viewport.dimensions[1] as u32],
+            None => [0, 0],
+        };
+        Renderer {
+            device,
+            queue,
+            render_pass,
+            pipelines: HashMap::new(),
+            command_buffer_builder: None,
+            dynamic_state,
+            camera: Camera::default(),
+            frames,
+            current_frame: 0,
+            coordinate_mode: CoordinateMode::Physical,
+            scale_factor: 1.0,
+            target_stack: Vec::new(),
+            target_passes: HashMap::new(),
+            target_commands: Vec::new(),
+            pipeline_cache,
+            virtual_resolution: None,
+            clip_stack: Vec::new(),
+            viewport_region: None,
+            frame_counter: FrameCounter::default(),
+            stats: FrameStats::default(),
+            output_srgb: false,
+            pipelines_added: false,
+            window_id: None,
+            framebuffer_size,
+            frame_index: 0,
+        }
+    }
+
+    /// Ratio of physical to logical pixels for the window being drawn to.
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// The window this renderer draws into, to tell windows apart in the
+    /// closure given to `MamgaGfx::run`. `None` when headless.
+    pub fn window_id(&self) -> Option<WindowId> {
+        self.window_id
+    }
+
+    /// Size of the framebuffer in physical pixels.
+    pub fn physical_size(&self) -> [f32; 2] {
+        [self.framebuffer_size[0] as f32, self.framebuffer_size[1] as f32]
+    }
+
+    /// Size of the framebuffer in logical pixels.
+    pub fn logical_size(&self) -> [f32; 2] {
+        let [width, height] = self.physical_size();
+        [width / self.scale_factor as f32, height / self.scale_factor as f32]
+    }
+
+    /// Size of the area draw coordinates map onto: the viewport region or
+    /// virtual resolution if there is one, otherwise the framebuffer in the
+    /// units of `coordinate_mode`.
+    pub(crate) fn projection_dimensions(&self) -> [f32; 2] {
+        if let Some(region) = &self.viewport_region {
+            return region.size;
+        }
+        if let Some(resolution) = self.virtual_resolution {
+            return [resolution.width as f32, resolution.height as f32];
+        }
+        match self.coordinate_mode {
+            CoordinateMode::Physical => self.physical_size(),
+            CoordinateMode::Logical => self.logical_size(),
+        }
+    }
+
+    /// Depth state for pipelines built against `render_pass`. Draws that
+    /// blend with what's behind them shouldn't write depth, or they would hide
+    /// anything drawn behind them later.
+    pub(crate) fn depth_stencil(&self, write: bool) -> DepthStencil {
+        if Subpass::from(self.render_pass.clone(), 0).unwrap().has_depth() {
+            DepthStencil {
+                depth_write: write,
+                depth_compare: Compare::LessOrEqual,
+                ..DepthStencil::disabled()
+            }
+        } else {
+            DepthStencil::disabled()
+        }
+    }
+
+    /// Converts a normalised `Color` into what the current framebuffer
+    /// expects: linear for sRGB formats, which encode on write, otherwise
+    /// unchanged.
+    pub(crate) fn output_rgba(&self, rgba: [f32; 4]) -> [f32; 4] {
+        if self.output_srgb { linear_rgba(rgba) } else { rgba }
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Waits for the GPU to finish with the next frame's resources and makes
+    /// it the current one.
+    pub(crate) fn advance_frame(&mut self) -> Result<(), MagmaError> {
+        self.end_frame_stats();
+        // Clips and viewports never carry over into the next frame.
+        self.clip_stack.clear();
+        self.apply_clip();
+        self.reset_viewport();
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
+        self.frame_index += 1;
+        self.frames[self.current_frame].wait()?;
+        self.collect_frame_times();
+        Ok(())
+    }
+
+    /// Takes the frame's builder out to record into, failing if no frame is
+    /// being recorded.
+    pub(crate) fn take_command_buffer_builder(&mut self) -> Result<AutoCommandBufferBuilder, MagmaError> {
+        self.command_buffer_builder.take().ok_or_else(|| {
+            MagmaError::Pipeline("no frame is being recorded".to_string())
+        })
+    }
+}
+
+
+/// Units for draw coordinates and sizes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateMode {
+    /// Framebuffer pixels, so sprites shrink on high DPI displays.
+    Physical,
+    /// Pixels scaled by the window's scale factor, so sprites keep the same
+    /// apparent size on every display.
+    Logical,
 }
 
 
@@ -44,6 +260,31 @@ impl MamgaGfx {
     pub fn clear(&mut self, color: Color) {
         self.clear_color = color.normalise();
     }
+
+    pub fn surface(&self) -> &Arc<Surface<winit::window::Window>> {
+        &self.output.surface
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.output.swapchain.present_mode()
+    }
+
+    pub fn image_count(&self) -> u32 {
+        self.output.swapchain.num_images()
+    }
+
+    pub fn surface_format(&self) -> Format {
+        self.output.swapchain.format()
+    }
+
+    /// Writes the pipeline cache to `MagmaConfig::pipeline_cache`, if set.
+    /// The run loop does this on exit.
+    pub fn save_pipeline_cache(&self) -> Result<(), MagmaError> {
+        match &self.config.pipeline_cache {
+            Some(path) => save_pipeline_cache(&self.renderer.pipeline_cache, path),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -79,9 +320,16 @@ pub struct Color {
 }
 
 impl Color {
+    /// Components scaled to `0.0..=1.0`, still sRGB encoded.
     pub fn normalise(&self) -> [f32; 4] {
         [self.r as f32 / 255., self.g as f32 / 255., self.b as f32 / 255., self.a as f32 / 255.]
     }
+
+    /// Components decoded from sRGB to linear, which is what shaders should
+    /// output into an sRGB framebuffer. Alpha is already linear.
+    pub fn to_linear(&self) -> [f32; 4] {
+        linear_rgba(self.normalise())
+    }
     pub fn new(r: u8, g: u8, b: u8) -> Self {
             Color {
                 r,
@@ -99,4 +347,19 @@ impl Color {
                     a
                 }
             }
-}
\ No newline at end of file
+}
+
+/// Whether attachments of `format` encode to sRGB when written and decode
+/// when sampled.
+pub fn is_srgb(format: Format) -> bool {
+    matches!(
+        format,
+        Format::R8G8B8A8Srgb | Format::B8G8R8A8Srgb | Format::A8B8G8R8SrgbPack32 | Format::R8G8B8Srgb | Format::B8G8R8Srgb
+    )
+}
+
+/// Decodes the color components of sRGB encoded `rgba` to linear.
+pub(crate) fn linear_rgba(rgba: [f32; 4]) -> [f32; 4] {
+    let decode = |c: f32| if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+    [decode(rgba[0]), decode(rgba[1]), decode(rgba[2]), rgba[3]]
+}
@@ -0,0 +1,185 @@
+use std::ffi::CString;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use vulkano::device::DeviceOwned;
+use vulkano::instance::debug::{DebugCallback, Message, MessageSeverity, MessageType};
+use vulkano::instance::{layers_list, Instance, InstanceExtensions};
+use vulkano::VulkanObject;
+
+use crate::error::MagmaError;
+
+/// Layer enabled by `DebugConfig` when the Vulkan SDK provides it.
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// How serious a validation message is, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DebugSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl DebugSeverity {
+    fn from_vulkano(severity: &MessageSeverity) -> Self {
+        if severity.error {
+            DebugSeverity::Error
+        } else if severity.warning {
+            DebugSeverity::Warning
+        } else if severity.information {
+            DebugSeverity::Info
+        } else {
+            DebugSeverity::Verbose
+        }
+    }
+
+    fn to_log(self) -> log::Level {
+        match self {
+            DebugSeverity::Verbose => log::Level::Trace,
+            DebugSeverity::Info => log::Level::Info,
+            DebugSeverity::Warning => log::Level::Warn,
+            DebugSeverity::Error => log::Level::Error,
+        }
+    }
+}
+
+/// A message reported by the validation layer or the driver.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugMessage<'a> {
+    pub severity: DebugSeverity,
+    /// Who reported it, usually `Validation` or `Loader Message`.
+    pub layer_prefix: &'a str,
+    pub description: &'a str,
+}
+
+/// Receives validation messages instead of the `log` crate.
+pub type DebugHandler = Arc<dyn Fn(&DebugMessage) + Send + Sync>;
+
+/// Enables `VK_LAYER_KHRONOS_validation` and reports what it finds.
+///
+/// Also names the pipelines, buffers and images magma-gfx creates, so
+/// validation messages and graphics debuggers can tell them apart.
+#[derive(Clone)]
+pub struct DebugConfig {
+    /// Messages below this are dropped.
+    pub min_severity: DebugSeverity,
+    /// Where messages go, `None` sends them to the `log` crate under the
+    /// `magma_gfx::vulkan` target.
+    pub handler: Option<DebugHandler>,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        DebugConfig {
+            min_severity: DebugSeverity::Warning,
+            handler: None,
+        }
+    }
+}
+
+impl DebugConfig {
+    pub fn new() -> Self {
+        DebugConfig::default()
+    }
+
+    pub fn with_min_severity(mut self, min_severity: DebugSeverity) -> Self {
+        self.min_severity = min_severity;
+        self
+    }
+
+    pub fn with_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&DebugMessage) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+}
+
+impl fmt::Debug for DebugConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DebugConfig")
+            .field("min_severity", &self.min_severity)
+            .field("handler", &self.handler.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+/// Creates the instance with `extensions`, plus the validation layer and
+/// debug utils extension when `debug` is set and they are installed.
+///
+/// The returned callback has to be kept alive for messages to keep arriving.
+pub(crate) fn create_instance(
+    extensions: &InstanceExtensions,
+    debug: Option<&DebugConfig>,
+) -> Result<(Arc<Instance>, Option<DebugCallback>), MagmaError> {
+    let debug = match debug {
+        Some(debug) => debug,
+        None => return Ok((Instance::new(None, extensions, None)?, None)),
+    };
+
+    let has_layer = layers_list()
+        .map(|mut layers| layers.any(|layer| layer.name() == VALIDATION_LAYER))
+        .unwrap_or(false);
+    let has_debug_utils = InstanceExtensions::supported_by_core()
+        .map(|supported| supported.ext_debug_utils)
+        .unwrap_or(false);
+    if !has_layer {
+        log::warn!(target: "magma_gfx::vulkan", "{} is not installed, validation is disabled", VALIDATION_LAYER);
+    }
+
+    let extensions = InstanceExtensions {
+        ext_debug_utils: has_debug_utils,
+        ..*extensions
+    };
+    let layers: Vec<&str> = if has_layer { vec![VALIDATION_LAYER] } else { vec![] };
+    let instance = Instance::new(None, &extensions, layers)?;
+
+    if !has_debug_utils {
+        return Ok((instance, None));
+    }
+
+    let min_severity = debug.min_severity;
+    let handler = debug.handler.clone();
+    // The handler is only ever called, never left half way through, so a
+    // panic inside it can't leave anything it captured in a broken state.
+    let handler = AssertUnwindSafe(handler);
+    let callback = DebugCallback::new(&instance, MessageSeverity::all(), MessageType::all(), move |msg: &Message| {
+        let message = DebugMessage {
+            severity: DebugSeverity::from_vulkano(&msg.severity),
+            layer_prefix: msg.layer_prefix,
+            description: msg.description,
+        };
+        if message.severity < min_severity {
+            return;
+        }
+        match &*handler {
+            Some(handler) => handler(&message),
+            None => log::log!(
+                target: "magma_gfx::vulkan",
+                message.severity.to_log(),
+                "[{}] {}",
+                message.layer_prefix,
+                message.description
+            ),
+        }
+    })?;
+
+    Ok((instance, Some(callback)))
+}
+
+/// Labels `object` for validation messages and graphics debuggers. Does
+/// nothing unless the instance was created with a `DebugConfig`.
+pub(crate) fn set_debug_name<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if !device.instance().loaded_extensions().ext_debug_utils {
+        return;
+    }
+    if let Ok(name) = CString::new(name) {
+        // Names are only a debugging aid, so failing to set one isn't worth
+        // failing the call that created the object.
+        let _ = device.set_object_name(object, &name);
+    }
+}
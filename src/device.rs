@@ -0,0 +1,240 @@
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use vulkano::instance::{Instance, PhysicalDevice, PhysicalDeviceType, QueueFamily, Version};
+use vulkano::swapchain::Surface;
+
+use crate::error::MagmaError;
+
+/// Environment variable that forces a device by its index in `list_devices`,
+/// overriding whatever `DeviceSelector` the application asked for.
+pub const DEVICE_INDEX_ENV: &str = "MAGMA_DEVICE_INDEX";
+
+/// Properties of a physical device, as reported by `list_devices`.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub ty: PhysicalDeviceType,
+    pub api_version: Version,
+    pub driver_version: u32,
+    pub vendor_id: u32,
+    pub device_id: u32,
+}
+
+impl DeviceInfo {
+    fn new(physical: &PhysicalDevice) -> Self {
+        DeviceInfo {
+            index: physical.index(),
+            name: physical.name().to_string(),
+            ty: physical.ty(),
+            api_version: physical.api_version(),
+            driver_version: physical.driver_version(),
+            vendor_id: physical.pci_vendor_id(),
+            device_id: physical.pci_device_id(),
+        }
+    }
+}
+
+/// How to choose between the physical devices on the machine.
+#[derive(Clone)]
+pub enum DeviceSelector {
+    /// Prefer discrete GPUs, then integrated, virtual and finally CPU devices.
+    Default,
+    /// Prefer devices of the given type, falling back to the default order.
+    Prefer(PhysicalDeviceType),
+    /// Only devices whose name contains this string, ignoring case.
+    Name(String),
+    /// Device at this index in `list_devices`.
+    Index(usize),
+    /// Highest scoring device. Returning `None` rules a device out.
+    Score(Arc<dyn Fn(&DeviceInfo) -> Option<u32> + Send + Sync>),
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        DeviceSelector::Default
+    }
+}
+
+impl fmt::Debug for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceSelector::Default => write!(f, "Default"),
+            DeviceSelector::Prefer(ty) => write!(f, "Prefer({:?})", ty),
+            DeviceSelector::Name(name) => write!(f, "Name({:?})", name),
+            DeviceSelector::Index(index) => write!(f, "Index({})", index),
+            DeviceSelector::Score(_) => write!(f, "Score(..)"),
+        }
+    }
+}
+
+pub fn list_devices(instance: &Arc<Instance>) -> Vec<DeviceInfo> {
+    PhysicalDevice::enumerate(instance)
+        .map(|physical| DeviceInfo::new(&physical))
+        .collect()
+}
+
+fn type_rank(ty: PhysicalDeviceType) -> u32 {
+    match ty {
+        PhysicalDeviceType::DiscreteGpu => 4,
+        PhysicalDeviceType::IntegratedGpu => 3,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 1,
+        PhysicalDeviceType::Other => 0,
+    }
+}
+
+fn score(selector: &DeviceSelector, info: &DeviceInfo) -> Option<u32> {
+    match selector {
+        DeviceSelector::Default => Some(type_rank(info.ty)),
+        DeviceSelector::Prefer(ty) if info.ty == *ty => Some(type_rank(info.ty) + 10),
+        DeviceSelector::Prefer(_) => Some(type_rank(info.ty)),
+        DeviceSelector::Name(name) => {
+            if info.name.to_lowercase().contains(&name.to_lowercase()) {
+                Some(type_rank(info.ty))
+            } else {
+                None
+            }
+        }
+        DeviceSelector::Index(index) if info.index == *index => Some(0),
+        DeviceSelector::Index(_) => None,
+        DeviceSelector::Score(f) => f(info),
+    }
+}
+
+/// Finds a graphics queue family on `physical`, which must also be able to
+/// present to `surface` when one is given.
+fn graphics_queue_family<'a, W>(
+    physical: PhysicalDevice<'a>,
+    surface: Option<&Arc<Surface<W>>>,
+) -> Option<QueueFamily<'a>> {
+    physical.queue_families().find(|&q| {
+        q.supports_graphics()
+            && surface.map_or(true, |surface| surface.is_supported(q).unwrap_or(false))
+    })
+}
+
+/// Picks the physical device and queue family to render with.
+///
+/// `MAGMA_DEVICE_INDEX` takes priority over `selector` when it is set. Devices
+/// without a graphics queue that can present to `surface` are never chosen.
+pub(crate) fn select_physical_device<'a, W>(
+    instance: &'a Arc<Instance>,
+    selector: &DeviceSelector,
+    surface: Option<&Arc<Surface<W>>>,
+) -> Result<(PhysicalDevice<'a>, QueueFamily<'a>), MagmaError> {
+    let from_env = env_selector(env::var(DEVICE_INDEX_ENV).ok().as_deref())?;
+    let selector = from_env.as_ref().unwrap_or(selector);
+
+    let candidates: Vec<_> = PhysicalDevice::enumerate(instance)
+        .filter_map(|physical| Some((physical, graphics_queue_family(physical, surface)?)))
+        .collect();
+    let devices: Vec<_> = candidates.iter().map(|(physical, _)| DeviceInfo::new(physical)).collect();
+    best_device(selector, &devices)
+        .map(|i| candidates[i])
+        .ok_or_else(|| MagmaError::DeviceSelection(format!("no usable device matches {:?}", selector)))
+}
+
+/// The selector `MAGMA_DEVICE_INDEX` asks for, given its value when set.
+fn env_selector(value: Option<&str>) -> Result<Option<DeviceSelector>, MagmaError> {
+    value
+        .map(|value| {
+            value.trim().parse().map(DeviceSelector::Index).map_err(|_| {
+                MagmaError::DeviceSelection(format!("{} must be a device index, got {:?}", DEVICE_INDEX_ENV, value))
+            })
+        })
+        .transpose()
+}
+
+/// Position in `devices` of the highest scoring one.
+fn best_device(selector: &DeviceSelector, devices: &[DeviceInfo]) -> Option<usize> {
+    devices
+        .iter()
+        .enumerate()
+        .filter_map(|(i, info)| Some((score(selector, info)?, i)))
+        // Keeps the first device on ties so enumeration order breaks them.
+        .fold(None, |best: Option<(u32, usize)>, candidate| match best {
+            Some(best) if best.0 >= candidate.0 => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(_, i)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(index: usize, name: &str, ty: PhysicalDeviceType) -> DeviceInfo {
+        DeviceInfo {
+            index,
+            name: name.to_string(),
+            ty,
+            api_version: Version { major: 1, minor: 1, patch: 0 },
+            driver_version: 0,
+            vendor_id: 0,
+            device_id: 0,
+        }
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            device(0, "llvmpipe", PhysicalDeviceType::Cpu),
+            device(1, "Intel UHD 630", PhysicalDeviceType::IntegratedGpu),
+            device(2, "GeForce GTX 1050", PhysicalDeviceType::DiscreteGpu),
+            device(3, "GeForce RTX 2080", PhysicalDeviceType::DiscreteGpu),
+        ]
+    }
+
+    #[test]
+    fn env_index_is_parsed() {
+        assert!(matches!(env_selector(None), Ok(None)));
+        assert!(matches!(env_selector(Some("2")), Ok(Some(DeviceSelector::Index(2)))));
+        assert!(matches!(env_selector(Some(" 1\n")), Ok(Some(DeviceSelector::Index(1)))));
+    }
+
+    #[test]
+    fn invalid_env_index_is_an_error() {
+        for value in ["", "gpu", "-1", "1.5", "99999999999999999999999"].iter() {
+            assert!(matches!(env_selector(Some(value)), Err(MagmaError::DeviceSelection(_))), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn default_prefers_discrete_then_enumeration_order() {
+        assert_eq!(best_device(&DeviceSelector::Default, &devices()), Some(2));
+        assert_eq!(best_device(&DeviceSelector::Default, &devices()[.. 2]), Some(1));
+        assert_eq!(best_device(&DeviceSelector::Default, &[]), None);
+    }
+
+    #[test]
+    fn prefer_outranks_the_default_order() {
+        let selector = DeviceSelector::Prefer(PhysicalDeviceType::Cpu);
+        assert_eq!(best_device(&selector, &devices()), Some(0));
+        let selector = DeviceSelector::Prefer(PhysicalDeviceType::VirtualGpu);
+        assert_eq!(best_device(&selector, &devices()), Some(2));
+    }
+
+    #[test]
+    fn name_matches_ignoring_case() {
+        let selector = DeviceSelector::Name("rtx".to_string());
+        assert_eq!(best_device(&selector, &devices()), Some(3));
+        let selector = DeviceSelector::Name("radeon".to_string());
+        assert_eq!(best_device(&selector, &devices()), None);
+    }
+
+    #[test]
+    fn index_out_of_range_matches_nothing() {
+        assert_eq!(best_device(&DeviceSelector::Index(1), &devices()), Some(1));
+        assert_eq!(best_device(&DeviceSelector::Index(4), &devices()), None);
+    }
+
+    #[test]
+    fn score_can_rule_devices_out() {
+        let selector = DeviceSelector::Score(Arc::new(|info: &DeviceInfo| {
+            if info.ty == PhysicalDeviceType::DiscreteGpu { None } else { Some(info.index as u32) }
+        }));
+        assert_eq!(best_device(&selector, &devices()), Some(1));
+    }
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use vulkano::buffer::cpu_access::ReadLockError;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilderContextError, BeginRenderPassError, BuildError,
+    CommandBufferExecError, CopyBufferImageError, DrawError,
+};
+use vulkano::descriptor::descriptor_set::{
+    PersistentDescriptorSetBuildError, PersistentDescriptorSetError,
+};
+use vulkano::device::DeviceCreationError;
+use vulkano::framebuffer::{FramebufferCreationError, RenderPassCreationError};
+use vulkano::image::ImageCreationError;
+use vulkano::instance::InstanceCreationError;
+use vulkano::instance::debug::DebugCallbackCreationError;
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::GraphicsPipelineCreationError;
+use vulkano::sampler::SamplerCreationError;
+use vulkano::swapchain::{AcquireError, CapabilitiesError, SwapchainCreationError};
+use vulkano::sync::FlushError;
+use vulkano::OomError;
+use vulkano_win::CreationError;
+
+/// Every way magma-gfx can fail, grouped by what was being attempted.
+#[derive(Debug)]
+pub enum MagmaError {
+    /// Creating the instance or device, or finding a usable GPU and queue.
+    DeviceSelection(String),
+    /// Creating the window surface or querying its capabilities.
+    Surface(String),
+    /// Creating, recreating, acquiring from or presenting to the swapchain.
+    Swapchain(String),
+    /// Submitting a frame's commands to the GPU, or waiting on them.
+    Flush(String),
+    /// A texture file was read but couldn't be decoded.
+    ImageDecode(image::ImageError),
+    Io(io::Error),
+    /// Allocating buffers, images or other device memory.
+    BufferAllocation(String),
+    /// Building pipelines, render passes, descriptor sets or recording draws.
+    Pipeline(String),
+}
+
+impl fmt::Display for MagmaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MagmaError::DeviceSelection(e) => write!(f, "device selection failed: {}", e),
+            MagmaError::Surface(e) => write!(f, "surface error: {}", e),
+            MagmaError::Swapchain(e) => write!(f, "swapchain error: {}", e),
+            MagmaError::Flush(e) => write!(f, "failed to flush frame: {}", e),
+            MagmaError::ImageDecode(e) => write!(f, "failed to decode image: {}", e),
+            MagmaError::Io(e) => write!(f, "io error: {}", e),
+            MagmaError::BufferAllocation(e) => write!(f, "buffer allocation failed: {}", e),
+            MagmaError::Pipeline(e) => write!(f, "pipeline error: {}", e),
+        }
+    }
+}
+
+impl Error for MagmaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MagmaError::ImageDecode(e) => Some(e),
+            MagmaError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MagmaError {
+    fn from(e: io::Error) -> Self {
+        MagmaError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for MagmaError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => MagmaError::Io(e),
+            e => MagmaError::ImageDecode(e),
+        }
+    }
+}
+
+macro_rules! impl_from_error {
+    ($variant:ident: $($error:ty),+) => {
+        $(
+            impl From<$error> for MagmaError {
+                fn from(e: $error) -> Self {
+                    MagmaError::$variant(e.to_string())
+                }
+            }
+        )+
+    };
+}
+
+impl_from_error!(DeviceSelection: InstanceCreationError, DeviceCreationError, DebugCallbackCreationError);
+impl_from_error!(Surface: CreationError, CapabilitiesError);
+impl_from_error!(Swapchain: SwapchainCreationError, AcquireError);
+impl_from_error!(Flush: FlushError);
+impl_from_error!(
+    BufferAllocation: DeviceMemoryAllocError,
+    ImageCreationError,
+    OomError,
+    ReadLockError
+);
+impl_from_error!(
+    Pipeline: GraphicsPipelineCreationError,
+    RenderPassCreationError,
+    FramebufferCreationError,
+    SamplerCreationError,
+    PersistentDescriptorSetError,
+    PersistentDescriptorSetBuildError,
+    DrawError,
+    BeginRenderPassError,
+    AutoCommandBufferBuilderContextError,
+    CopyBufferImageError,
+    BuildError,
+    CommandBufferExecError
+);
+#[cfg(feature = "glsl")]
+impl_from_error!(Pipeline: shaderc::Error);
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::cpu_pool::CpuBufferPool;
+use vulkano::descriptor::descriptor_set::FixedSizeDescriptorSetsPool;
+use vulkano::device::{Device, Queue};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::sync::{FenceSignalFuture, FlushError, GpuFuture};
+
+use crate::core::{Vertex2D, Vertex2DColor};
+use crate::shapes::rec_vs;
+use crate::stats::GpuTimes;
+use crate::textures::texture_vs;
+use crate::timestamps::FrameTimer;
+
+/// Transient resources owned by one frame in flight.
+///
+/// The buffer pools and descriptor pools only hand out memory and sets that
+/// were freed by earlier uses of this same frame, and `wait` blocks until the
+/// GPU has finished with them, so nothing is reused while still in flight and
+/// nothing is allocated again once the pools have grown to fit a frame.
+pub(crate) struct FrameResources {
+    pub(crate) fence: Option<FenceSignalFuture<Box<dyn GpuFuture>>>,
+    pub(crate) rect_vertices: CpuBufferPool<Vertex2DColor>,
+    pub(crate) rect_uniforms: CpuBufferPool<rec_vs::ty::Data>,
+    pub(crate) texture_vertices: CpuBufferPool<Vertex2D>,
+    pub(crate) texture_uniforms: CpuBufferPool<texture_vs::ty::Data>,
+    descriptor_pools: HashMap<String, FixedSizeDescriptorSetsPool>,
+    /// When the submission behind `fence` was flushed.
+    pub(crate) submitted_at: Option<Instant>,
+    /// How long that submission took, once it's seen to be done.
+    pub(crate) frame_latency: Option<Duration>,
+    /// `None` when the queue can't write timestamps.
+    pub(crate) timer: Option<FrameTimer>,
+    /// GPU time of that submission, once it's seen to be done.
+    pub(crate) gpu_times: Option<GpuTimes>,
+}
+
+impl FrameResources {
+    pub(crate) fn new(device: Arc<Device>, queue: &Queue) -> Self {
+        FrameResources {
+            timer: FrameTimer::new(device.clone(), queue),
+            gpu_times: None,
+            fence: None,
+            rect_vertices: CpuBufferPool::new(device.clone(), BufferUsage::vertex_buffer()),
+            rect_uniforms: CpuBufferPool::new(device.clone(), BufferUsage::uniform_buffer()),
+            texture_vertices: CpuBufferPool::new(device.clone(), BufferUsage::vertex_buffer()),
+            texture_uniforms: CpuBufferPool::new(device, BufferUsage::uniform_buffer()),
+            descriptor_pools: HashMap::new(),
+            submitted_at: None,
+            frame_latency: None,
+        }
+    }
+
+    /// Blocks until the GPU has finished the last submission that used these
+    /// resources.
+    pub(crate) fn wait(&mut self) -> Result<(), FlushError> {
+        if let Some(fence) = self.fence.take() {
+            fence.wait(None)?;
+            self.finished();
+        }
+        Ok(())
+    }
+
+    /// Releases what the last submission held if it's done, and records its
+    /// latency and GPU time.
+    pub(crate) fn poll(&mut self) {
+        if let Some(fence) = self.fence.as_mut() {
+            fence.cleanup_finished();
+            if self.submitted_at.is_some() && fence.wait(Some(Duration::from_secs(0))).is_ok() {
+                self.finished();
+            }
+        }
+    }
+
+    /// Records the latency and GPU time of a submission that is done.
+    pub(crate) fn finished(&mut self) {
+        if let Some(submitted_at) = self.submitted_at.take() {
+            self.frame_latency = Some(submitted_at.elapsed());
+        }
+        if let Some(timer) = self.timer.as_mut() {
+            self.gpu_times = timer.read();
+        }
+    }
+
+    /// Descriptor pool for set 0 of the pipeline stored under `name`.
+    pub(crate) fn descriptor_pool(
+        &mut self,
+        name: &str,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    ) -> &mut FixedSizeDescriptorSetsPool {
+        self.descriptor_pools.entry(name.to_string()).or_insert_with(|| {
+            FixedSizeDescriptorSetsPool::new(pipeline.descriptor_set_layout(0).unwrap().clone())
+        })
+    }
+}
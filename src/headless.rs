@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::device::{Device, DeviceExtensions, Features, Queue};
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract};
+use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage};
+use vulkano::instance::{Instance, InstanceExtensions};
+use vulkano::instance::debug::DebugCallback;
+use vulkano::pipeline::viewport::{Scissor, Viewport};
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use crate::cache::{load_pipeline_cache, save_pipeline_cache};
+use crate::core::{Color, Renderer};
+use crate::{clear_values, create_render_pass};
+use crate::debug::{create_instance, set_debug_name, DebugConfig};
+use crate::device::{select_physical_device, DeviceSelector};
+use crate::error::MagmaError;
+
+/// Format of the offscreen color image, chosen so `read_rgba` can hand the
+/// bytes back without any swizzling.
+const HEADLESS_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// Renders into an offscreen image instead of a window, for machines without
+/// a display. Frames are only rendered when `step` is called.
+pub struct HeadlessGfx {
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    pub instance: Arc<Instance>,
+    pub clear_color: [f32; 4],
+    pub renderer: Renderer,
+    pub image: Arc<AttachmentImage>,
+    pub dimensions: [u32; 2],
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    pipeline_cache: Option<PathBuf>,
+    _debug_callback: Option<DebugCallback>,
+}
+
+impl HeadlessGfx {
+    pub fn clear(&mut self, color: Color) {
+        self.clear_color = color.normalise();
+    }
+
+    /// Records the draw calls made by `update` into the offscreen image and
+    /// waits for the GPU to finish them.
+    pub fn step<F>(&mut self, mut update: F) -> Result<(), MagmaError>
+    where
+        F: FnMut(&mut Renderer) -> Result<(), MagmaError>,
+    {
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.queue.family(),
+        )?;
+        builder.begin_render_pass(
+            self.framebuffer.clone(),
+            false,
+            clear_values(self.clear_color, false, 1),
+        )?;
+        self.renderer.command_buffer_builder = Some(builder);
+
+        update(&mut self.renderer)?;
+        self.renderer.end_targets()?;
+
+        let mut builder = self.renderer.take_command_buffer_builder()?;
+        builder.end_render_pass()?;
+        let command_buffer = builder.build()?;
+
+        let future = self.renderer.execute_target_passes(sync::now(self.device.clone()).boxed())?;
+        let future = self.renderer.execute_frame(future, command_buffer)?;
+        let future = future.then_signal_fence_and_flush()?;
+        self.renderer.frame_submitted();
+        future.wait(None)?;
+        self.renderer.frames[self.renderer.current_frame].finished();
+        self.renderer.advance_frame()
+    }
+
+    /// Copies the last rendered frame back to the CPU as tightly packed RGBA8
+    /// rows, top row first.
+    pub fn read_rgba(&self) -> Result<Vec<u8>, MagmaError> {
+        let [width, height] = self.dimensions;
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::all(),
+            false,
+            (0 .. width * height * 4).map(|_| 0u8),
+        )?;
+        set_debug_name(buffer.inner().buffer, "magma headless readback");
+
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.queue.family(),
+        )?;
+        builder.copy_image_to_buffer(self.image.clone(), buffer.clone())?;
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        let pixels = buffer.read()?;
+        Ok(pixels.to_vec())
+    }
+
+    /// Writes the pipeline cache to the path given to
+    /// `init_headless_with_cache`, if any.
+    pub fn save_pipeline_cache(&self) -> Result<(), MagmaError> {
+        match &self.pipeline_cache {
+            Some(path) => save_pipeline_cache(&self.renderer.pipeline_cache, path),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn init_headless(width: u32, height: u32) -> Result<HeadlessGfx, MagmaError> {
+    init_headless_with(width, height, DeviceSelector::default())
+}
+
+pub fn init_headless_with(
+    width: u32,
+    height: u32,
+    selector: DeviceSelector,
+) -> Result<HeadlessGfx, MagmaError> {
+    init_headless_with_debug(width, height, selector, None)
+}
+
+/// Like `init_headless_with`, with validation enabled when `debug` is set.
+pub fn init_headless_with_debug(
+    width: u32,
+    height: u32,
+    selector: DeviceSelector,
+    debug: Option<DebugConfig>,
+) -> Result<HeadlessGfx, MagmaError> {
+    init_headless_with_cache(width, height, selector, debug, None)
+}
+
+/// Like `init_headless_with_debug`, seeding the pipeline cache from
+/// `pipeline_cache` so repeated runs, e.g. on CI, skip shader compilation.
+/// Call `HeadlessGfx::save_pipeline_cache` to write it back.
+pub fn init_headless_with_cache(
+    width: u32,
+    height: u32,
+    selector: DeviceSelector,
+    debug: Option<DebugConfig>,
+    pipeline_cache: Option<PathBuf>,
+) -> Result<HeadlessGfx, MagmaError> {
+    let (instance, debug_callback) = create_instance(&InstanceExtensions::none(), debug.as_ref())?;
+    let (physical, queue_family) = select_physical_device::<()>(&instance, &selector, None)?;
+
+    let (device, mut queues) = Device::new(
+        physical,
+        &Features::none(),
+        &DeviceExtensions::none(),
+        [(queue_family, 0.5)].iter().cloned(),
+    )?;
+    let queue = queues.next().unwrap();
+
+    let dimensions = [width, height];
+    let image = AttachmentImage::with_usage(
+        device.clone(),
+        dimensions,
+        HEADLESS_FORMAT,
+        ImageUsage {
+            color_attachment: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        },
+    )?;
+    set_debug_name(image.inner().image, "magma headless color");
+
+    let render_pass = create_render_pass(device.clone(), HEADLESS_FORMAT, false, 1)?;
+
+    let framebuffer = Arc::new(
+        Framebuffer::start(render_pass.clone())
+            .add(image.clone())?
+            .build()?,
+    ) as Arc<dyn FramebufferAbstract + Send + Sync>;
+
+    let dynamic_state = DynamicState {
+        viewports: Some(vec![Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [width as f32, height as f32],
+            depth_range: 0.0..1.0,
+        }]),
+        scissors: Some(vec![Scissor::irrelevant()]),
+        ..DynamicState::none()
+    };
+
+    let cache = load_pipeline_cache(&device, pipeline_cache.as_deref())?;
+    let renderer = Renderer::new(device.clone(), queue.clone(), render_pass, dynamic_state, 1, cache);
+
+    Ok(HeadlessGfx {
+        device,
+        queue,
+        instance,
+        clear_color: [0.1, 0.1, 0.1, 1.0],
+        renderer,
+        image,
+        dimensions,
+        framebuffer,
+        pipeline_cache,
+        _debug_callback: debug_callback,
+    })
+}
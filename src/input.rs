@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+
+use glam::Vec2;
+use winit::event::{ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::resolution::VirtualPlacement;
+
+/// Keyboard, mouse and text input collected by the run loop.
+///
+/// "Pressed" and "released" only hold for the frame the change happened in,
+/// "down" holds for as long as the key or button is held.
+#[derive(Debug)]
+pub struct Input {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>,
+    keys_released: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    mouse_position: Vec2,
+    scroll_delta: Vec2,
+    text: String,
+    scale_factor: f64,
+    virtual_placement: Option<VirtualPlacement>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::new()
+    }
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            keys_down: HashSet::new(),
+            keys_pressed: HashSet::new(),
+            keys_released: HashSet::new(),
+            buttons_down: HashSet::new(),
+            buttons_pressed: HashSet::new(),
+            buttons_released: HashSet::new(),
+            mouse_position: Vec2::zero(),
+            scroll_delta: Vec2::zero(),
+            text: String::new(),
+            scale_factor: 1.0,
+            virtual_placement: None,
+        }
+    }
+
+    pub(crate) fn with_scale_factor(mut self, scale_factor: f64) -> Self {
+        self.scale_factor = scale_factor;
+        self
+    }
+
+    pub(crate) fn with_virtual_placement(mut self, placement: Option<VirtualPlacement>) -> Self {
+        self.virtual_placement = placement;
+        self
+    }
+
+    pub(crate) fn set_virtual_placement(&mut self, placement: Option<VirtualPlacement>) {
+        self.virtual_placement = placement;
+    }
+
+    pub fn key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn key_released(&self, key: VirtualKeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn mouse_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn mouse_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    /// Cursor position in framebuffer pixels from the top left of the window.
+    pub fn mouse_position(&self) -> Vec2 {
+        self.mouse_position
+    }
+
+    /// Cursor position in logical pixels, matching draw coordinates in
+    /// `CoordinateMode::Logical`.
+    pub fn mouse_position_logical(&self) -> Vec2 {
+        self.mouse_position / self.scale_factor as f32
+    }
+
+    /// Cursor position in the coordinates of the `VirtualResolution`.
+    /// `None` without one, or while the cursor is over the letterbox bars.
+    pub fn mouse_position_virtual(&self) -> Option<Vec2> {
+        self.virtual_placement.and_then(|placement| placement.to_virtual(self.mouse_position))
+    }
+
+    /// Scroll since the last frame, in lines for wheels and pixels for touchpads.
+    pub fn scroll_delta(&self) -> Vec2 {
+        self.scroll_delta
+    }
+
+    /// Characters typed since the last frame.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput { state, virtual_keycode: Some(key), .. },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    // Key repeat sends more presses while the key is held.
+                    if self.keys_down.insert(*key) {
+                        self.keys_pressed.insert(*key);
+                    }
+                }
+                ElementState::Released => {
+                    self.keys_down.remove(key);
+                    self.keys_released.insert(*key);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons_down.insert(*button);
+                    self.buttons_pressed.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons_down.remove(button);
+                    self.buttons_released.insert(*button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.mouse_position = Vec2::new(position.x as f32, position.y as f32);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (*x, *y),
+                    MouseScrollDelta::PixelDelta(position) => (position.x as f32, position.y as f32),
+                };
+                self.scroll_delta += Vec2::new(x, y);
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.scale_factor = *scale_factor;
+            }
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                self.text.push(*c);
+            }
+            // Releases that happen while unfocused never arrive, so forget
+            // everything held rather than leave keys stuck down.
+            WindowEvent::Focused(false) => {
+                self.keys_down.clear();
+                self.buttons_down.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Clears the per-frame state once the update callback has seen it.
+    pub(crate) fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.scroll_delta = Vec2::zero();
+        self.text.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::{LogicalPosition, PhysicalPosition};
+    use winit::event::{DeviceId, ModifiersState, TouchPhase};
+
+    fn device_id() -> DeviceId {
+        unsafe { DeviceId::dummy() }
+    }
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn button(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput { device_id: device_id(), state, button, modifiers: ModifiersState::empty() }
+    }
+
+    #[allow(deprecated)]
+    fn cursor(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: device_id(),
+            position: PhysicalPosition::new(x, y),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn wheel(delta: MouseScrollDelta) -> WindowEvent<'static> {
+        WindowEvent::MouseWheel {
+            device_id: device_id(),
+            delta,
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[test]
+    fn key_presses_and_releases_last_one_frame() {
+        let mut input = Input::new();
+        input.handle_event(&key(VirtualKeyCode::Space, ElementState::Pressed));
+        assert!(input.key_pressed(VirtualKeyCode::Space));
+        assert!(input.key_down(VirtualKeyCode::Space));
+        assert!(!input.key_released(VirtualKeyCode::Space));
+
+        input.end_frame();
+        // Key repeat doesn't count as another press.
+        input.handle_event(&key(VirtualKeyCode::Space, ElementState::Pressed));
+        assert!(!input.key_pressed(VirtualKeyCode::Space));
+        assert!(input.key_down(VirtualKeyCode::Space));
+
+        input.end_frame();
+        input.handle_event(&key(VirtualKeyCode::Space, ElementState::Released));
+        assert!(input.key_released(VirtualKeyCode::Space));
+        assert!(!input.key_down(VirtualKeyCode::Space));
+
+        input.end_frame();
+        assert!(!input.key_released(VirtualKeyCode::Space));
+        assert!(!input.key_pressed(VirtualKeyCode::Space));
+    }
+
+    #[test]
+    fn tap_within_one_frame_is_pressed_and_released() {
+        let mut input = Input::new();
+        input.handle_event(&button(MouseButton::Left, ElementState::Pressed));
+        input.handle_event(&button(MouseButton::Left, ElementState::Released));
+        assert!(input.mouse_pressed(MouseButton::Left));
+        assert!(input.mouse_released(MouseButton::Left));
+        assert!(!input.mouse_down(MouseButton::Left));
+
+        input.end_frame();
+        assert!(!input.mouse_pressed(MouseButton::Left));
+        assert!(!input.mouse_released(MouseButton::Left));
+    }
+
+    #[test]
+    fn buttons_stay_down_across_frames() {
+        let mut input = Input::new();
+        input.handle_event(&button(MouseButton::Right, ElementState::Pressed));
+        input.end_frame();
+        assert!(input.mouse_down(MouseButton::Right));
+        assert!(!input.mouse_pressed(MouseButton::Right));
+        assert!(!input.mouse_down(MouseButton::Left));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything_held() {
+        let mut input = Input::new();
+        input.handle_event(&key(VirtualKeyCode::W, ElementState::Pressed));
+        input.handle_event(&button(MouseButton::Left, ElementState::Pressed));
+        input.handle_event(&WindowEvent::Focused(false));
+        assert!(!input.key_down(VirtualKeyCode::W));
+        assert!(!input.mouse_down(MouseButton::Left));
+    }
+
+    #[test]
+    fn mouse_position_persists_across_frames() {
+        let mut input = Input::new().with_scale_factor(2.0);
+        input.handle_event(&cursor(100.0, 50.0));
+        input.end_frame();
+        assert_eq!(input.mouse_position(), Vec2::new(100.0, 50.0));
+        assert_eq!(input.mouse_position_logical(), Vec2::new(50.0, 25.0));
+        assert_eq!(input.mouse_position_virtual(), None);
+    }
+
+    #[test]
+    fn scroll_and_text_accumulate_then_clear() {
+        let mut input = Input::new();
+        input.handle_event(&wheel(MouseScrollDelta::LineDelta(0.0, 1.0)));
+        input.handle_event(&wheel(MouseScrollDelta::LineDelta(0.5, 2.0)));
+        input.handle_event(&wheel(MouseScrollDelta::PixelDelta(LogicalPosition::new(3.0, -1.0))));
+        input.handle_event(&WindowEvent::ReceivedCharacter('h'));
+        input.handle_event(&WindowEvent::ReceivedCharacter('\u{8}'));
+        input.handle_event(&WindowEvent::ReceivedCharacter('i'));
+        assert_eq!(input.scroll_delta(), Vec2::new(3.5, 2.0));
+        assert_eq!(input.text(), "hi");
+
+        input.end_frame();
+        assert_eq!(input.scroll_delta(), Vec2::zero());
+        assert_eq!(input.text(), "");
+    }
+}
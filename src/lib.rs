@@ -2,176 +2,430 @@ pub mod core;
 pub mod textures;
 pub mod shapes;
 pub mod camera;
+pub mod headless;
+pub mod config;
+pub mod error;
+pub mod device;
+pub mod timing;
+pub mod input;
+pub mod app;
+mod frames;
+mod screenshot;
+pub mod recorder;
+pub mod debug;
+pub mod windows;
+pub mod target;
+pub mod post;
+mod reflect;
+mod cache;
+mod clip;
+mod timestamps;
+mod viewport;
+pub mod stats;
+pub mod material;
+pub mod resolution;
 
 
 extern crate nalgebra_glm as glm;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use std::collections::HashMap;
+use image::RgbaImage;
 
-use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::command_buffer::DynamicState;
 use vulkano::device::{Device, DeviceExtensions, Features};
+use vulkano::format::Format;
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract};
-use vulkano::image::SwapchainImage;
-use vulkano::instance::{Instance, PhysicalDevice};
-use vulkano::pipeline::viewport::Viewport;
-use vulkano::swapchain::{AcquireError};
+use vulkano::format::ClearValue;
+use vulkano::image::{AttachmentImage, ImageAccess, ImageViewAccess, SwapchainImage};
+use vulkano::instance::PhysicalDevice;
+use vulkano::pipeline::viewport::{Scissor, Viewport};
 use vulkano::swapchain::{
-    ColorSpace, FullscreenExclusive, PresentMode, SurfaceTransform, Swapchain,
+    FullscreenExclusive, PresentMode, SurfaceTransform, Swapchain,
 };
-use vulkano::sync;
-use vulkano::sync::FlushError;
-use vulkano::sync::GpuFuture;
 use vulkano_win::VkSurfaceBuild;
 
 
 use winit::{
     event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    platform::desktop::EventLoopExtDesktop,
+    window::{Fullscreen, WindowBuilder},
 };
 
 
 
-use crate::core::{ MamgaGfx, Renderer };
-use crate::camera::{Camera, get_projection_matrix};
+use crate::core::{ is_srgb, MamgaGfx, Renderer };
+use crate::config::{MagmaConfig, WindowMode};
+use crate::error::MagmaError;
+use crate::device::select_physical_device;
+use crate::timing::{FrameClock, FrameContext};
+use crate::input::Input;
+use crate::app::{App, ClosureApp};
+use crate::screenshot::{readback_buffer, CaptureTarget, PendingCapture, ScreenshotWriter};
+use crate::recorder::{Recorder, RecordingConfig};
+use crate::camera::get_projection_matrix;
+use crate::debug::{create_instance, set_debug_name};
+use crate::windows::{share_pipelines, WindowOutput};
+use crate::post::PostProcess;
+use crate::cache::load_pipeline_cache;
+use crate::resolution::VirtualResolution;
 
 
-pub struct Window {
-    pub event_loop: EventLoop<()>
+pub struct Window<T: 'static = ()> {
+    pub event_loop: EventLoop<T>
 }
 
 
 impl MamgaGfx {
-    pub fn run<F>(mut self, window: Window, mut update: F)
+    /// Runs `update` every frame until the window is closed. Windows opened
+    /// with `create_window` beforehand are drawn with `update` too, after the
+    /// main one; `Renderer::window_id` tells them apart.
+    pub fn run<T: 'static, F>(self, window: Window<T>, update: F) -> Result<(), MagmaError>
     where
-        F: 'static + FnMut(&mut Renderer) -> (),
+        F: 'static + FnMut(&mut Renderer, &FrameContext, &Input) -> Result<(), MagmaError>,
     {
-        let physical = PhysicalDevice::enumerate(&self.instance)
-            .next()
-            .expect("no device available");
-
-        let mut framebuffers =
-            window_size_dependent_setup(&self.images, self.renderer.render_pass.clone(), &mut self.renderer.dynamic_state);
-        let mut recreate_swapchain = false;
-        let mut previous_frame_end =
-            Some(Box::new(sync::now(self.device.clone())) as Box<dyn GpuFuture>);
-            
-
-        window.event_loop.run(move |event, _, control_flow| {
-            *control_flow = ControlFlow::Poll;
-            match event {
-                Event::WindowEvent {
-                    event: WindowEvent::CloseRequested,
-                    ..
-                } => {
-                    println!("The close button was pressed; stopping");
-                    *control_flow = ControlFlow::Exit;
-                }
-                Event::WindowEvent {
-                    event: WindowEvent::Resized(_),
-                    ..
-                } => {
-                    recreate_swapchain = true;
-                }
-                Event::RedrawEventsCleared => {
-                    previous_frame_end.as_mut().unwrap().cleanup_finished();
-                    
-                    if recreate_swapchain {
-                        let dimensions: [u32; 2] = self.surface.window().inner_size().into();
-                        let (new_swapchain, new_images) = match self.swapchain
-                            .recreate_with_dimensions(dimensions)
-                        {
-                            Ok(r) => r,
-                            Err(
-                                vulkano::swapchain::SwapchainCreationError::UnsupportedDimensions,
-                            ) => return,
-                            Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
-                        };
-                        self.swapchain = new_swapchain;
-                        framebuffers = window_size_dependent_setup(
-                            &new_images,
-                            self.renderer.render_pass.clone(),
-                            &mut self.renderer.dynamic_state,
-                        );
-                        self.images = new_images;
-                        recreate_swapchain = false;
-                    }
+        self.run_app(window, ClosureApp(update))
+    }
 
-                    let (image_num, suboptimal, acquire_future) =
-                        match vulkano::swapchain::acquire_next_image(self.swapchain.clone(), None) {
-                            Ok(r) => r,
-                            Err(AcquireError::OutOfDate) => {
-                                recreate_swapchain = true;
-                                return;
-                            }
-                            Err(e) => panic!("Failed to acquire next image: {:?}", e),
-                        };
-                    if suboptimal {
-                        recreate_swapchain = true;
-                    }
+    /// Runs `app` until the window is closed, then exits the process.
+    ///
+    /// Only returns if `App::init` fails. Errors while running stop the loop
+    /// and are reported through the `log` crate, since the process exits
+    /// once it ends; use `run_app_return` to get them back instead.
+    pub fn run_app<T: 'static, A>(mut self, window: Window<T>, mut app: A) -> Result<(), MagmaError>
+    where
+        A: 'static + App<T>,
+    {
+        app.init(&mut self)?;
 
-                    self.renderer.command_buffer_builder = Option::from(AutoCommandBufferBuilder::primary_one_time_submit(
-                        self.device.clone(),
-                        self.queue.family(),
-                    )
-                    .unwrap()
-                    .begin_render_pass(
-                        framebuffers[image_num].clone(),
-                        false,
-                        vec![self.clear_color.into()],
-                    )
-                    .unwrap());
-
-                    update(&mut self.renderer);
-
-                    let command_buffer = self.renderer.command_buffer_builder.take().unwrap().end_render_pass()
-                        .unwrap()
-                        .build().unwrap();
-                    
-
-                    let future = previous_frame_end
-                        .take()
-                        .unwrap()
-                        .join(acquire_future)
-                        .then_execute(self.queue.clone(), command_buffer)
-                        .unwrap()
-                        .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num)
-                        .then_signal_fence_and_flush();
-
-                    match future {
-                        Ok(future) => {
-                            previous_frame_end = Some(Box::new(future) as Box<_>);
-                        }
-                        Err(FlushError::OutOfDate) => {
-                            recreate_swapchain = true;
-                            previous_frame_end =
-                                Some(Box::new(sync::now(self.device.clone())) as Box<_>);
-                        }
-                        Err(e) => {
-                            println!("Failed to flush future: {:?}", e);
-                            previous_frame_end =
-                                Some(Box::new(sync::now(self.device.clone())) as Box<_>);
-                        }
-                    }
-                }
-                _ => {}
+        window.event_loop.run(move |event, target, control_flow| {
+            if let Err(e) = self.handle_app_event(&mut app, event, target, control_flow) {
+                log::error!("failed to render frame: {}; stopping", e);
+                *control_flow = ControlFlow::Exit;
+            }
+        })
+    }
+
+    /// Like `run`, but returns once the window is closed instead of exiting
+    /// the process.
+    pub fn run_return<T: 'static, F>(&mut self, window: &mut Window<T>, update: F) -> Result<(), MagmaError>
+    where
+        F: FnMut(&mut Renderer, &FrameContext, &Input) -> Result<(), MagmaError>,
+    {
+        self.run_app_return(window, &mut ClosureApp(update))
+    }
+
+    /// Like `run_app`, but returns once the window is closed instead of
+    /// exiting the process.
+    pub fn run_app_return<T: 'static, A>(&mut self, window: &mut Window<T>, app: &mut A) -> Result<(), MagmaError>
+    where
+        A: App<T>,
+    {
+        app.init(self)?;
+
+        let mut result = Ok(());
+        window.event_loop.run_return(|event, target, control_flow| {
+            if let Err(e) = self.handle_app_event(app, event, target, control_flow) {
+                result = Err(e);
+                *control_flow = ControlFlow::Exit;
             }
         });
+        result
+    }
+
+    fn handle_app_event<T: 'static, A>(
+        &mut self,
+        app: &mut A,
+        event: Event<T>,
+        target: &EventLoopWindowTarget<T>,
+        control_flow: &mut ControlFlow,
+    ) -> Result<(), MagmaError>
+    where
+        A: App<T>,
+    {
+        *control_flow = ControlFlow::Poll;
+        app.on_event(&event);
+        self.handle_event(&event);
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                window_id,
+            } if window_id != self.output.surface.window().id() => {
+                self.close_window(window_id)?;
+                app.on_window_closed(window_id);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                log::info!("the close button was pressed; stopping");
+                *control_flow = ControlFlow::Exit;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                window_id,
+            } if window_id == self.output.surface.window().id() => {
+                app.on_resize([size.width, size.height]);
+            }
+            Event::MainEventsCleared => {
+                app.manage_windows(self, target)?;
+            }
+            Event::RedrawEventsCleared => {
+                let result = match self.begin_frame() {
+                    Ok(Some(frame)) => app
+                        .frame(&mut self.renderer, &frame, &self.input)
+                        .and_then(|_| self.end_frame())
+                        .map(|_| Some(frame)),
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                let result = result.and_then(|frame| self.draw_windows(app, frame));
+                self.clock.limit();
+                return result;
+            }
+            Event::LoopDestroyed => {
+                app.on_exit();
+                // Waits for screenshots still being written.
+                self.screenshot_writer = None;
+                self.save_pipeline_cache()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Draws every extra window with `App::draw_window`, reusing the main
+    /// window's `frame` so all windows see the same time step.
+    fn draw_windows<T: 'static, A>(&mut self, app: &mut A, frame: Option<FrameContext>) -> Result<(), MagmaError>
+    where
+        A: App<T>,
+    {
+        if self.windows.is_empty() {
+            return Ok(());
+        }
+        // The main window skips frames while minimised, but the others
+        // should keep drawing.
+        let frame = match frame {
+            Some(frame) => frame,
+            None => self.clock.tick(),
+        };
+        share_pipelines(&mut self.renderer, &mut self.windows);
+        for window in self.windows.iter_mut() {
+            if window.begin_frame(&self.config)? {
+                app.draw_window(window.id, &mut window.renderer, &frame, &window.input)?;
+                window.end_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Captures the next presented frame, to be collected with
+    /// `take_screenshot` once the GPU has finished it.
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_request = Some(CaptureTarget::Keep);
+    }
+
+    /// Captures the next presented frame and writes it to `path`, in whichever
+    /// format the extension names.
+    pub fn request_screenshot_to<P: Into<PathBuf>>(&mut self, path: P) {
+        self.screenshot_request = Some(CaptureTarget::Save(path.into()));
+    }
+
+    /// The last frame captured by `request_screenshot`, if it is ready.
+    pub fn take_screenshot(&mut self) -> Option<RgbaImage> {
+        self.screenshot.take()
+    }
+
+    /// Starts recording presented frames, stopping any recording already in
+    /// progress first.
+    pub fn start_recording(&mut self, config: RecordingConfig) -> Result<(), MagmaError> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::start(config, self.renderer.frames_in_flight())?);
+        Ok(())
+    }
+
+    /// Stops recording and waits for every captured frame to be written.
+    pub fn stop_recording(&mut self) -> Result<(), MagmaError> {
+        if let Some(recorder) = self.recorder.take() {
+            for frame in self.renderer.frames.iter_mut() {
+                frame.wait()?;
+            }
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    fn poll_screenshot(&mut self) -> Result<(), MagmaError> {
+        let image = match &self.pending_screenshot {
+            Some((capture, _)) => match capture.try_read()? {
+                Some(image) => image,
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        match self.pending_screenshot.take().unwrap().1 {
+            CaptureTarget::Keep => self.screenshot = Some(image),
+            CaptureTarget::Save(path) => self.screenshot_writer
+                .get_or_insert_with(ScreenshotWriter::new)
+                .save(image, path),
+        }
+        Ok(())
     }
+
+    /// Feeds a winit event to magma-gfx when the application owns the event
+    /// loop and drives frames with `begin_frame`/`end_frame`.
+    pub fn handle_event<T>(&mut self, event: &Event<T>) {
+        if let Event::WindowEvent { event: window_event, window_id } = event {
+            if *window_id != self.output.surface.window().id() {
+                if let Some(window) = self.window(*window_id) {
+                    window.handle_event(window_event);
+                }
+                return;
+            }
+            self.input.handle_event(window_event);
+            self.output.handle_event(&mut self.renderer, window_event);
+        }
+    }
+
+    /// Acquires the next swapchain image and starts recording into
+    /// `self.renderer`.
+    ///
+    /// Returns `None` when there is nothing to draw into this time, e.g. while
+    /// the window is minimised, in which case `end_frame` must not be called.
+    pub fn begin_frame(&mut self) -> Result<Option<FrameContext>, MagmaError> {
+        self.renderer.poll_frames();
+        self.poll_screenshot()?;
+        let recording_done = match self.recorder.as_mut() {
+            Some(recorder) => {
+                recorder.poll()?;
+                recorder.is_finished()
+            }
+            None => false,
+        };
+        if recording_done {
+            self.stop_recording()?;
+        }
+        
+        if self.output.recreate_swapchain {
+            self.post_process.invalidate();
+        }
+        let image_num = match self.output.acquire(&mut self.renderer, &mut self.input, &self.config)? {
+            Some(image_num) => image_num,
+            None => return Ok(None),
+        };
+
+        // With post-processing the scene goes into an intermediate image that
+        // the passes read from on the way to the swapchain image.
+        let framebuffer = if self.post_process.is_active() {
+            self.post_process.prepare(
+                self.device.clone(),
+                self.renderer.pipeline_cache.clone(),
+                &self.output.images,
+                self.renderer.render_pass.clone(),
+                self.config.depth_buffer,
+                self.config.samples,
+                self.renderer.frames_in_flight(),
+                self.renderer.current_frame,
+            )?
+        } else {
+            self.output.framebuffer(self.renderer.current_frame, image_num)
+        };
+        self.renderer.begin_frame_pass(framebuffer, self.clear_color, &self.config)?;
+
+        let frame = self.clock.tick();
+        self.post_process.time = frame.elapsed;
+        Ok(Some(frame))
+    }
+
+    /// Submits everything drawn since `begin_frame` and presents it. A failed
+    /// submission is returned as `MagmaError::Flush`.
+    pub fn end_frame(&mut self) -> Result<(), MagmaError> {
+        let image_num = self.output.acquired_image()?;
+        self.input.end_frame();
+
+        let mut builder = self.renderer.end_frame_pass()?;
+        if self.post_process.recording {
+            self.post_process.run(&mut builder, self.renderer.current_frame, image_num)?;
+        }
+        let screenshot = match self.screenshot_request.take() {
+            Some(target) => {
+                let image = self.output.images[image_num].clone();
+                let buffer = readback_buffer(self.device.clone(), image.dimensions())?;
+                let capture = PendingCapture::record(
+                    &mut builder,
+                    image.clone(),
+                    buffer,
+                    image.dimensions(),
+                    self.output.swapchain.format(),
+                )?;
+                Some((capture, target))
+            }
+            None => None,
+        };
+        let recording = match self.recorder.as_mut().and_then(|recorder| recorder.wants_frame().map(|slot| (recorder, slot))) {
+            Some((recorder, slot)) => {
+                let image = self.output.images[image_num].clone();
+                let buffer = recorder.buffers.get(self.device.clone(), self.renderer.current_frame, image.dimensions())?;
+                let capture = PendingCapture::record(
+                    &mut builder,
+                    image.clone(),
+                    buffer,
+                    image.dimensions(),
+                    self.output.swapchain.format(),
+                )?;
+                Some((capture, slot))
+            }
+            None => None,
+        };
+        let submitted = self.output.present(&mut self.renderer, builder);
+        if let (Some((capture, slot)), Ok(true)) = (recording, &submitted) {
+            // Only submitted frames can be read back. Otherwise the next
+            // capture fills this slot as well.
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.push(capture, slot);
+            }
+        }
+        if let Some((capture, target)) = screenshot {
+            // The copy never ran if the frame wasn't submitted, so ask again
+            // next frame instead of reading back an empty buffer.
+            if let Ok(true) = submitted {
+                self.pending_screenshot = Some((capture, target));
+            } else {
+                self.screenshot_request = Some(target);
+            }
+        }
+        submitted?;
+        Ok(())
+    }
+}
+
+pub fn init_renderer() -> Result<(MamgaGfx, Window), MagmaError> {
+    init_renderer_with(MagmaConfig::default())
+}
+
+pub fn init_renderer_with(config: MagmaConfig) -> Result<(MamgaGfx, Window), MagmaError> {
+    init_renderer_with_user_event(config)
 }
 
-pub fn init_renderer() -> (MamgaGfx, Window) {
+/// Like `init_renderer_with`, but the window's event loop carries custom
+/// events of type `T`, which `App::on_event` receives.
+pub fn init_renderer_with_user_event<T: 'static>(
+    mut config: MagmaConfig,
+) -> Result<(MamgaGfx, Window<T>), MagmaError> {
     let required_extensions = vulkano_win::required_extensions();
-    let instance =
-        Instance::new(None, &required_extensions, None).expect("failed to create instance");
-    let physical = PhysicalDevice::enumerate(&instance)
-        .next()
-        .expect("no device available");
-    let queue_family = physical
-        .queue_families()
-        .find(|&q| q.supports_graphics())
-        .expect("couldn't find a graphical queue family");
+    let (instance, debug_callback) = create_instance(&required_extensions, config.debug.as_ref())?;
+
+    let event_loop = EventLoop::with_user_event();
+
+    let surface = window_builder(&event_loop, &mut config)
+        .build_vk_surface(&event_loop, instance.clone())?;
+
+    let (physical, queue_family) = select_physical_device(&instance, &config.device, Some(&surface))?;
 
     let (device, mut queues) = Device::new(
         physical,
@@ -181,32 +435,43 @@ pub fn init_renderer() -> (MamgaGfx, Window) {
             ..vulkano::device::DeviceExtensions::none()
         },
         [(queue_family, 0.5)].iter().cloned(),
-    )
-    .expect("failed to create device");
+    )?;
     let queue = queues.next().unwrap();
+    let pipeline_cache = load_pipeline_cache(&device, config.pipeline_cache.as_deref())?;
 
-    use winit::dpi::LogicalSize;
-    let _window = WindowBuilder::new().with_inner_size(LogicalSize::new(1200, 800));
-
-
-    
-
-    let event_loop = EventLoop::new();
-    let surface = _window
-        .build_vk_surface(&event_loop, instance.clone())
-        .unwrap();
-
-    let (mut swapchain, images) = {
-        let caps = surface.capabilities(physical).unwrap();
+    let (swapchain, images) = {
+        let caps = surface.capabilities(physical)?;
         let usage = caps.supported_usage_flags;
-        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
-        let format = caps.supported_formats[0].0;
+        let alpha = caps.supported_composite_alpha.iter().next()
+            .ok_or_else(|| MagmaError::Surface("no supported composite alpha mode".to_string()))?;
+        let (format, color_space) = config
+            .surface_format
+            .and_then(|preferred| caps.supported_formats.iter().find(|&&(f, _)| f == preferred))
+            // sRGB, so colors look the same on every machine.
+            .or_else(|| caps.supported_formats.iter().find(|&&(f, _)| is_srgb(f)))
+            .or_else(|| caps.supported_formats.first())
+            .cloned()
+            .ok_or_else(|| MagmaError::Surface("surface supports no formats".to_string()))?;
+        // Fifo is the only present mode every surface is required to support.
+        let present_mode = if caps.present_modes.supports(config.present_mode) {
+            config.present_mode
+        } else {
+            PresentMode::Fifo
+        };
+        let mut image_count = config.image_count.unwrap_or(caps.min_image_count).max(caps.min_image_count);
+        if let Some(max) = caps.max_image_count {
+            image_count = image_count.min(max);
+        }
         let dimensions: [u32; 2] = surface.window().inner_size().into();
 
+        config.surface_format = Some(format);
+        config.present_mode = present_mode;
+        config.image_count = Some(image_count);
+
         Swapchain::new(
             device.clone(),
             surface.clone(),
-            caps.min_image_count,
+            image_count,
             format,
             dimensions,
             1,
@@ -214,31 +479,15 @@ pub fn init_renderer() -> (MamgaGfx, Window) {
             &queue,
             SurfaceTransform::Identity,
             alpha,
-            PresentMode::Fifo,
+            present_mode,
             FullscreenExclusive::Default,
             true,
-            ColorSpace::SrgbNonLinear,
-        )
-        .unwrap()
+            color_space,
+        )?
     };
     
-    let render_pass = Arc::new(
-        vulkano::single_pass_renderpass!(device.clone(),
-            attachments: {
-                color: {
-                    load: Clear,
-                    store: Store,
-                    format: swapchain.format(),
-                    samples: 1,
-                }
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {}
-            }
-        )
-        .unwrap(),
-    );
+    config.samples = supported_samples(physical, config.samples, config.depth_buffer);
+    let render_pass = create_render_pass(device.clone(), swapchain.format(), config.depth_buffer, config.samples)?;
 
     let dimensions = images[0].dimensions();
 
@@ -246,61 +495,413 @@ pub fn init_renderer() -> (MamgaGfx, Window) {
         viewports: Some(vec![Viewport {
             origin: [0.0, 0.0],
             dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-            depth_range: -1.0..1.0,
+            depth_range: 0.0..1.0,
         }]),
         ..DynamicState::none()
     };
 
-    let renderer = Renderer {
-        dynamic_state,
-        device: device.clone(),
-        pipelines: HashMap::new(),
+    let framebuffers = window_size_dependent_setup(
+        device.clone(),
+        &images,
+        render_pass.clone(),
+        &mut dynamic_state,
+        config.depth_buffer,
+        config.samples,
+        config.frames_in_flight,
+        config.virtual_resolution.as_ref(),
+    )?;
+    let clock = FrameClock::new(config.target_fps, config.fixed_timestep);
+
+    let mut renderer = Renderer::new(
+        device.clone(),
+        queue.clone(),
         render_pass,
-        command_buffer_builder: None,
-        camera: Camera::default()
-    };
-
-    (
+        dynamic_state,
+        config.frames_in_flight,
+        pipeline_cache,
+    );
+    let scale_factor = surface.window().scale_factor();
+    renderer.coordinate_mode = config.coordinate_mode;
+    renderer.scale_factor = scale_factor;
+    renderer.virtual_resolution = config.virtual_resolution;
+    renderer.output_srgb = is_srgb(swapchain.format());
+    renderer.window_id = Some(surface.window().id());
+    renderer.framebuffer_size = dimensions;
+    let virtual_placement = config.virtual_resolution.map(|resolution| resolution.placement(dimensions));
+
+    Ok((
         MamgaGfx {
             queue,
             device,
             instance,
             clear_color: [0.1, 0.1, 0.1, 1.0],
-            swapchain,
-            images,
-            surface,
-            renderer
+            renderer,
+            config,
+            input: Input::new()
+                .with_scale_factor(scale_factor)
+                .with_virtual_placement(virtual_placement),
+            output: WindowOutput::new(surface, swapchain, images, framebuffers),
+            screenshot_request: None,
+            pending_screenshot: None,
+            screenshot: None,
+            screenshot_writer: None,
+            recorder: None,
+            clock,
+            _debug_callback: debug_callback,
+            windows: Vec::new(),
+            post_process: PostProcess::new(),
         },
         Window {
             event_loop,
         },
-    )
+    ))
 }
 
-fn window_size_dependent_setup(
+/// Window settings shared by the main window and `MamgaGfx::create_window`.
+///
+/// Exclusive fullscreen falls back to windowed when the monitor lists no
+/// video modes, and `config.window_mode` is updated to match.
+pub(crate) fn window_builder<T: 'static>(target: &EventLoopWindowTarget<T>, config: &mut MagmaConfig) -> WindowBuilder {
+    use winit::dpi::LogicalSize;
+    let fullscreen = match config.window_mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(target.primary_monitor())),
+        WindowMode::Fullscreen => {
+            let mode = target.primary_monitor().video_modes().next();
+            if mode.is_none() {
+                config.window_mode = WindowMode::Windowed;
+            }
+            mode.map(Fullscreen::Exclusive)
+        }
+    };
+    WindowBuilder::new()
+        .with_title(config.title.clone())
+        .with_inner_size(LogicalSize::new(config.width, config.height))
+        .with_resizable(config.resizable)
+        .with_fullscreen(fullscreen)
+}
+
+/// Format of the optional depth attachment, picked because every Vulkan
+/// implementation supports it as a depth attachment.
+pub(crate) const DEPTH_FORMAT: Format = Format::D16Unorm;
+
+/// Builds the render pass shared by the windowed and headless renderers, so
+/// pipelines created against one are valid for the other.
+///
+/// With `samples` above 1, drawing goes to a multisampled color attachment
+/// that is resolved into `format` at the end of the pass. Pipelines take their
+/// sample count from the subpass, so anything built against the returned pass
+/// matches it.
+pub(crate) fn create_render_pass(
+    device: Arc<Device>,
+    format: Format,
+    depth_buffer: bool,
+    samples: u32,
+) -> Result<Arc<dyn RenderPassAbstract + Send + Sync>, MagmaError> {
+    let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = match (depth_buffer, samples > 1) {
+        (false, false) => Arc::new(
+            vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )?,
+        ),
+        (true, false) => Arc::new(
+            vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )?,
+        ),
+        (false, true) => Arc::new(
+            vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    multisampled: {
+                        load: Clear,
+                        store: DontCare,
+                        format: format,
+                        samples: samples,
+                    },
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [multisampled],
+                    depth_stencil: {},
+                    resolve: [color],
+                }
+            )?,
+        ),
+        (true, true) => Arc::new(
+            vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    multisampled: {
+                        load: Clear,
+                        store: DontCare,
+                        format: format,
+                        samples: samples,
+                    },
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: DEPTH_FORMAT,
+                        samples: samples,
+                    }
+                },
+                pass: {
+                    color: [multisampled],
+                    depth_stencil: {depth},
+                    resolve: [color],
+                }
+            )?,
+        ),
+    };
+    Ok(render_pass)
+}
+
+/// Clear values matching the attachments made by `create_render_pass`.
+pub(crate) fn clear_values(color: [f32; 4], depth_buffer: bool, samples: u32) -> Vec<ClearValue> {
+    let mut values = vec![color.into()];
+    if samples > 1 {
+        // The resolve target is loaded with `DontCare`.
+        values.push(ClearValue::None);
+    }
+    if depth_buffer {
+        values.push(1f32.into());
+    }
+    values
+}
+
+/// Highest sample count up to `requested` that the device supports for the
+/// attachments `create_render_pass` would make.
+pub(crate) fn supported_samples(physical: PhysicalDevice, requested: u32, depth_buffer: bool) -> u32 {
+    let limits = physical.limits();
+    let mut supported = limits.framebuffer_color_sample_counts();
+    if depth_buffer {
+        supported &= limits.framebuffer_depth_sample_counts();
+    }
+    [8, 4, 2]
+        .iter()
+        .cloned()
+        .find(|&samples| samples <= requested && supported & samples != 0)
+        .unwrap_or(1)
+}
+
+pub(crate) fn window_size_dependent_setup(
+    device: Arc<Device>,
     images: &[Arc<SwapchainImage<winit::window::Window>>],
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     dynamic_state: &mut DynamicState,
-) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
+    depth_buffer: bool,
+    samples: u32,
+    frames_in_flight: usize,
+    virtual_resolution: Option<&VirtualResolution>,
+) -> Result<Vec<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>, MagmaError> {
     let dimensions = images[0].dimensions();
+    let format = images[0].swapchain().format();
 
-    let viewport = Viewport {
-        origin: [0.0, 0.0],
-        dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-        depth_range: -1.0..1.0,
+    // A virtual resolution only draws into its scaled rectangle, leaving the
+    // letterbox bars around it untouched.
+    let viewport = match virtual_resolution {
+        Some(resolution) => resolution.placement(dimensions).viewport(),
+        None => Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+            depth_range: 0.0..1.0,
+        },
     };
     dynamic_state.viewports = Some(vec![viewport]);
+    dynamic_state.scissors = Some(vec![Scissor::irrelevant()]);
 
-    images
-        .iter()
-        .map(|image| {
-            Arc::new(
-                Framebuffer::start(render_pass.clone())
-                    .add(image.clone())
-                    .unwrap()
-                    .build()
-                    .unwrap(),
-            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+    scene_framebuffers(device, images, render_pass, dimensions, format, depth_buffer, samples, frames_in_flight)
+}
+
+/// Framebuffers for every swapchain image, once per frame in flight, indexed
+/// by frame and then by image.
+///
+/// Frames in flight can be on the GPU at the same time, so each one gets its
+/// own depth and multisampled attachments instead of two frames writing the
+/// same image.
+pub(crate) fn scene_framebuffers<I>(
+    device: Arc<Device>,
+    images: &[I],
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    dimensions: [u32; 2],
+    format: Format,
+    depth_buffer: bool,
+    samples: u32,
+    frames_in_flight: usize,
+) -> Result<Vec<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>, MagmaError>
+where
+    I: ImageViewAccess + Clone + Send + Sync + 'static,
+{
+    (0 .. frames_in_flight.max(1))
+        .map(|_| {
+            let attachments = SceneAttachments::new(device.clone(), dimensions, format, depth_buffer, samples)?;
+            images
+                .iter()
+                .map(|image| attachments.framebuffer(render_pass.clone(), image.clone()))
+                .collect::<Result<Vec<_>, MagmaError>>()
         })
-        .collect::<Vec<_>>()
+        .collect()
+}
+
+/// The attachments `create_render_pass` adds around the color image. Only one
+/// frame can draw into them at a time, so frames in flight each need their own.
+pub(crate) struct SceneAttachments {
+    multisampled: Option<Arc<AttachmentImage>>,
+    depth: Option<Arc<AttachmentImage>>,
+}
+
+impl SceneAttachments {
+    pub(crate) fn new(
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        format: Format,
+        depth_buffer: bool,
+        samples: u32,
+    ) -> Result<SceneAttachments, MagmaError> {
+        let multisampled = if samples > 1 {
+            let image = AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, format)?;
+            set_debug_name(image.inner().image, "magma multisampled color");
+            Some(image)
+        } else {
+            None
+        };
+        let depth = if depth_buffer {
+            let image = AttachmentImage::transient_multisampled(device, dimensions, samples, DEPTH_FORMAT)?;
+            set_debug_name(image.inner().image, "magma depth");
+            Some(image)
+        } else {
+            None
+        };
+        Ok(SceneAttachments { multisampled, depth })
+    }
+
+    /// Framebuffer for `render_pass` that ends up in `image`.
+    pub(crate) fn framebuffer<I>(
+        &self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        image: I,
+    ) -> Result<Arc<dyn FramebufferAbstract + Send + Sync>, MagmaError>
+    where
+        I: ImageViewAccess + Send + Sync + 'static,
+    {
+        Ok(match (&self.multisampled, &self.depth) {
+            (None, None) => Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(image)?
+                    .build()?,
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>,
+            (None, Some(depth)) => Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(image)?
+                    .add(depth.clone())?
+                    .build()?,
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>,
+            (Some(multisampled), None) => Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(multisampled.clone())?
+                    .add(image)?
+                    .build()?,
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>,
+            (Some(multisampled), Some(depth)) => Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(multisampled.clone())?
+                    .add(image)?
+                    .add(depth.clone())?
+                    .build()?,
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vulkano::command_buffer::AutoCommandBufferBuilder;
+    use vulkano::image::ImageUsage;
+    use vulkano::sync::{self, GpuFuture};
+
+    use crate::headless::init_headless;
+
+    /// Submits two frames in flight, each into its own color image, without
+    /// either waiting for the other.
+    fn submit_frames_back_to_back(samples: u32) -> Result<(), MagmaError> {
+        let gfx = init_headless(64, 64)?;
+        let device = gfx.device.clone();
+        let format = Format::R8G8B8A8Unorm;
+        let samples = supported_samples(device.physical_device(), samples, true);
+        let render_pass = create_render_pass(device.clone(), format, true, samples)?;
+        let images = (0 .. 2)
+            .map(|_| {
+                AttachmentImage::with_usage(device.clone(), [64, 64], format, ImageUsage {
+                    color_attachment: true,
+                    ..ImageUsage::none()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let framebuffers = scene_framebuffers(device.clone(), &images, render_pass, [64, 64], format, true, samples, 2)?;
+
+        let mut futures = Vec::new();
+        for frame in 0 .. 2 {
+            let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), gfx.queue.family())?;
+            builder.begin_render_pass(framebuffers[frame][frame].clone(), false, clear_values([0.0; 4], true, samples))?;
+            builder.end_render_pass()?;
+            let future = sync::now(device.clone())
+                .then_execute(gfx.queue.clone(), builder.build()?)?
+                .then_signal_fence_and_flush()?;
+            futures.push(future);
+        }
+        for future in futures {
+            future.wait(None)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn frames_in_flight_have_their_own_depth() {
+        submit_frames_back_to_back(1).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn frames_in_flight_have_their_own_multisampled_color() {
+        submit_frames_back_to_back(4).unwrap();
+    }
 }
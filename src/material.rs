@@ -0,0 +1,592 @@
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use glam::{Mat4, Vec2};
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::cpu_pool::CpuBufferPool;
+use vulkano::descriptor::descriptor::{
+    DescriptorBufferDesc, DescriptorDesc, DescriptorDescTy, DescriptorImageDesc,
+    DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages,
+};
+use vulkano::descriptor::descriptor_set::DescriptorSet;
+use vulkano::descriptor::pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange};
+use vulkano::device::Device;
+use vulkano::framebuffer::Subpass;
+use vulkano::image::ImageViewAccess;
+use vulkano::pipeline::shader::{GraphicsShaderType, ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule};
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::sampler::Sampler;
+
+use crate::cache::build_pipeline;
+use crate::camera::get_projection_matrix_z;
+use crate::core::{Color, Renderer};
+use crate::debug::set_debug_name;
+use crate::error::MagmaError;
+use crate::reflect::{
+    reflect, InterfaceVariable, Reflection, UniformBlock, EXECUTION_MODEL_FRAGMENT, EXECUTION_MODEL_VERTEX,
+};
+use crate::shapes::Rectangle;
+use crate::textures::Texture2D;
+
+pub use crate::reflect::UniformKind;
+
+/// Most samplers a material can bind.
+const MAX_SAMPLERS: usize = 4;
+
+/// Name of the uniform that material draws fill with the draw's transform.
+const MVP_UNIFORM: &str = "mvp";
+
+/// Vertex fed to material shaders. A vertex shader declares whichever of
+/// `position`, `uv` and `color` it uses as inputs with those names.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MaterialVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+vulkano::impl_vertex!(MaterialVertex, position, uv, color);
+
+impl MaterialVertex {
+    pub fn new(position: [f32; 2], uv: [f32; 2], color: [f32; 4]) -> Self {
+        MaterialVertex { position, uv, color }
+    }
+}
+
+/// A vertex and fragment shader pair loaded at runtime, with the interface
+/// read from the SPIR-V.
+///
+/// Descriptors must all be in set 0: an optional uniform block at binding 0,
+/// followed by up to four `sampler2D`s at the next bindings. A `mat4` uniform
+/// named `mvp` is filled with the transform of each draw.
+pub struct Shader {
+    device: Arc<Device>,
+    vertex: Arc<ShaderModule>,
+    fragment: Arc<ShaderModule>,
+    /// Entry point names, read from each module's `OpEntryPoint`.
+    vertex_entry: CString,
+    fragment_entry: CString,
+    vertex_reflection: Reflection,
+    fragment_reflection: Reflection,
+    uniform_block: Option<UniformBlock>,
+    samplers: Vec<String>,
+}
+
+impl Shader {
+    /// Compiles GLSL sources for the vertex and fragment stages. Needs the
+    /// `glsl` feature.
+    #[cfg(feature = "glsl")]
+    pub fn from_glsl(device: Arc<Device>, vertex: &str, fragment: &str) -> Result<Arc<Shader>, MagmaError> {
+        let vertex = compile(vertex, shaderc::ShaderKind::Vertex, "material.vert")?;
+        let fragment = compile(fragment, shaderc::ShaderKind::Fragment, "material.frag")?;
+        Shader::from_spirv(device, &vertex, &fragment)
+    }
+
+    pub fn from_spirv(device: Arc<Device>, vertex: &[u8], fragment: &[u8]) -> Result<Arc<Shader>, MagmaError> {
+        let vertex_reflection = reflect(vertex)?;
+        let fragment_reflection = reflect(fragment)?;
+        let vertex_entry = entry_point(&vertex_reflection, EXECUTION_MODEL_VERTEX, "vertex")?;
+        let fragment_entry = entry_point(&fragment_reflection, EXECUTION_MODEL_FRAGMENT, "fragment")?;
+
+        let uniform_block = match (&vertex_reflection.uniform_block, &fragment_reflection.uniform_block) {
+            (Some(a), Some(b)) if a.binding != b.binding || a.size != b.size => {
+                return Err(MagmaError::Pipeline("the stages declare different uniform blocks".to_string()));
+            }
+            (Some(block), _) | (None, Some(block)) => Some(block.clone()),
+            (None, None) => None,
+        };
+        if let Some(block) = &uniform_block {
+            if block.binding != 0 {
+                return Err(MagmaError::Pipeline("the uniform block must be at binding 0".to_string()));
+            }
+        }
+
+        let first_sampler = if uniform_block.is_some() { 1 } else { 0 };
+        let mut samplers: Vec<(u32, String)> = Vec::new();
+        for sampler in vertex_reflection.samplers.iter().chain(fragment_reflection.samplers.iter()) {
+            if !samplers.iter().any(|&(binding, _)| binding == sampler.binding) {
+                samplers.push((sampler.binding, sampler.name.clone()));
+            }
+        }
+        samplers.sort();
+        for (i, &(binding, _)) in samplers.iter().enumerate() {
+            if binding != first_sampler + i as u32 {
+                return Err(MagmaError::Pipeline(format!(
+                    "sampler bindings must follow on from binding {} without gaps",
+                    first_sampler
+                )));
+            }
+        }
+        if samplers.len() > MAX_SAMPLERS {
+            return Err(MagmaError::Pipeline(format!("materials can bind at most {} samplers", MAX_SAMPLERS)));
+        }
+
+        // Safe as long as the SPIR-V is valid, which the driver can't be
+        // relied on to check.
+        let vertex = unsafe { ShaderModule::new(device.clone(), vertex)? };
+        let fragment = unsafe { ShaderModule::new(device.clone(), fragment)? };
+
+        Ok(Arc::new(Shader {
+            device,
+            vertex,
+            fragment,
+            vertex_entry,
+            fragment_entry,
+            vertex_reflection,
+            fragment_reflection,
+            uniform_block,
+            samplers: samplers.into_iter().map(|(_, name)| name).collect(),
+        }))
+    }
+
+    /// Uniforms the shader declares that `Material::set` can fill.
+    pub fn uniforms(&self) -> Vec<(&str, UniformKind)> {
+        self.uniform_block
+            .iter()
+            .flat_map(|block| block.members.iter())
+            .map(|member| (member.name.as_str(), member.kind))
+            .collect()
+    }
+
+    /// Sampler names, in binding order.
+    pub fn samplers(&self) -> &[String] {
+        &self.samplers
+    }
+}
+
+fn entry_point(reflection: &Reflection, execution_model: u32, stage: &str) -> Result<CString, MagmaError> {
+    let name = reflection.entry_point(execution_model)
+        .ok_or_else(|| MagmaError::Pipeline(format!("the {} module has no {} entry point", stage, stage)))?;
+    CString::new(name).map_err(|_| MagmaError::Pipeline(format!("invalid {} entry point name", stage)))
+}
+
+#[cfg(feature = "glsl")]
+fn compile(source: &str, kind: shaderc::ShaderKind, name: &str) -> Result<Vec<u8>, MagmaError> {
+    let mut compiler = shaderc::Compiler::new()
+        .ok_or_else(|| MagmaError::Pipeline("couldn't start the GLSL compiler".to_string()))?;
+    let artifact = compiler.compile_into_spirv(source, kind, name, "main", None)?;
+    Ok(artifact.as_binary_u8().to_vec())
+}
+
+/// A value for one uniform of a material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UniformValue {
+    Float(f32),
+    Int(i32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat4([[f32; 4]; 4]),
+}
+
+impl UniformValue {
+    fn kind(&self) -> UniformKind {
+        match self {
+            UniformValue::Float(_) => UniformKind::Float,
+            UniformValue::Int(_) => UniformKind::Int,
+            UniformValue::Vec2(_) => UniformKind::Vec2,
+            UniformValue::Vec3(_) => UniformKind::Vec3,
+            UniformValue::Vec4(_) => UniformKind::Vec4,
+            UniformValue::Mat4(_) => UniformKind::Mat4,
+        }
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        let floats: Vec<f32> = match *self {
+            UniformValue::Int(value) => {
+                bytes[.. 4].copy_from_slice(&value.to_ne_bytes());
+                return;
+            }
+            UniformValue::Float(value) => vec![value],
+            UniformValue::Vec2(value) => value.to_vec(),
+            UniformValue::Vec3(value) => value.to_vec(),
+            UniformValue::Vec4(value) => value.to_vec(),
+            UniformValue::Mat4(value) => value.iter().flat_map(|column| column.to_vec()).collect(),
+        };
+        for (i, float) in floats.iter().enumerate() {
+            bytes[i * 4 .. i * 4 + 4].copy_from_slice(&float.to_ne_bytes());
+        }
+    }
+}
+
+impl From<f32> for UniformValue {
+    fn from(value: f32) -> Self {
+        UniformValue::Float(value)
+    }
+}
+
+impl From<i32> for UniformValue {
+    fn from(value: i32) -> Self {
+        UniformValue::Int(value)
+    }
+}
+
+impl From<[f32; 2]> for UniformValue {
+    fn from(value: [f32; 2]) -> Self {
+        UniformValue::Vec2(value)
+    }
+}
+
+impl From<Vec2> for UniformValue {
+    fn from(value: Vec2) -> Self {
+        UniformValue::Vec2([value.x(), value.y()])
+    }
+}
+
+impl From<[f32; 3]> for UniformValue {
+    fn from(value: [f32; 3]) -> Self {
+        UniformValue::Vec3(value)
+    }
+}
+
+impl From<[f32; 4]> for UniformValue {
+    fn from(value: [f32; 4]) -> Self {
+        UniformValue::Vec4(value)
+    }
+}
+
+/// Passes the color as stored. Use `Color::to_linear` for colors a shader
+/// writes to an sRGB framebuffer.
+impl From<Color> for UniformValue {
+    fn from(value: Color) -> Self {
+        UniformValue::Vec4(value.normalise())
+    }
+}
+
+impl From<Mat4> for UniformValue {
+    fn from(value: Mat4) -> Self {
+        UniformValue::Mat4([value.x_axis().into(), value.y_axis().into(), value.z_axis().into(), value.w_axis().into()])
+    }
+}
+
+/// A `Shader` plus the uniform values and textures to draw it with.
+pub struct Material {
+    name: String,
+    shader: Arc<Shader>,
+    uniforms: Vec<u8>,
+    textures: Vec<Option<(Arc<dyn ImageViewAccess + Send + Sync>, Arc<Sampler>)>>,
+    vertices: CpuBufferPool<MaterialVertex>,
+    uniform_buffers: CpuBufferPool<u8>,
+}
+
+impl Material {
+    /// `name` identifies the material's pipelines, so materials with
+    /// different shaders need different names.
+    pub fn new<N: Into<String>>(name: N, shader: Arc<Shader>) -> Material {
+        let size = shader.uniform_block.as_ref().map_or(0, |block| block.size);
+        Material {
+            name: name.into(),
+            uniforms: vec![0; size],
+            textures: vec![None; shader.samplers.len()],
+            vertices: CpuBufferPool::new(shader.device.clone(), BufferUsage::vertex_buffer()),
+            uniform_buffers: CpuBufferPool::new(shader.device.clone(), BufferUsage::uniform_buffer()),
+            shader,
+        }
+    }
+
+    pub fn shader(&self) -> &Arc<Shader> {
+        &self.shader
+    }
+
+    /// Sets the uniform called `name`, which must have the value's type.
+    pub fn set<V: Into<UniformValue>>(&mut self, name: &str, value: V) -> Result<(), MagmaError> {
+        let value = value.into();
+        let member = self
+            .shader
+            .uniform_block
+            .as_ref()
+            .and_then(|block| block.members.iter().find(|member| member.name == name))
+            .ok_or_else(|| MagmaError::Pipeline(format!("material has no uniform named {}", name)))?;
+        if member.kind != value.kind() {
+            return Err(MagmaError::Pipeline(format!(
+                "uniform {} is a {:?}, not a {:?}",
+                name,
+                member.kind,
+                value.kind()
+            )));
+        }
+        value.write(&mut self.uniforms[member.offset ..]);
+        Ok(())
+    }
+
+    /// Binds `texture` to the sampler called `name`.
+    pub fn set_texture(&mut self, name: &str, texture: &Texture2D) -> Result<(), MagmaError> {
+        let index = self
+            .shader
+            .samplers
+            .iter()
+            .position(|sampler| sampler == name)
+            .ok_or_else(|| MagmaError::Pipeline(format!("material has no sampler named {}", name)))?;
+        self.textures[index] = Some((texture.image.clone(), texture.sampler.clone()));
+        Ok(())
+    }
+}
+
+/// Adds the sampled images to a descriptor set builder in binding order.
+/// A macro because each added descriptor changes the builder's type.
+macro_rules! material_set {
+    ($builder:expr; $($texture:expr),*) => {{
+        let builder = $builder;
+        $( let builder = builder.add_sampled_image($texture.0.clone(), $texture.1.clone())?; )*
+        Arc::new(builder.build()?) as Arc<dyn DescriptorSet + Send + Sync>
+    }};
+}
+
+impl Renderer {
+    /// Draws `rectangle` with `material`, passing `color` and 0..1 uvs as
+    /// vertex attributes.
+    pub fn material_rectangle(
+        &mut self,
+        material: &mut Material,
+        rectangle: &Rectangle,
+        color: Color,
+        z: f32,
+    ) -> Result<(), MagmaError> {
+        let size = Vec2::new(rectangle.width as f32, rectangle.height as f32);
+        let color = self.output_rgba(color.normalise());
+        self.material_quad(material, size, rectangle.position, color, z)
+    }
+
+    /// Draws `texture` with `material`, bound to the material's first
+    /// sampler.
+    pub fn material_texture(
+        &mut self,
+        material: &mut Material,
+        texture: &Texture2D,
+        position: Vec2,
+        scale: f32,
+        z: f32,
+    ) -> Result<(), MagmaError> {
+        if material.textures.is_empty() {
+            return Err(MagmaError::Pipeline("material has no sampler to bind the texture to".to_string()));
+        }
+        material.textures[0] = Some((texture.image.clone(), texture.sampler.clone()));
+        let size = Vec2::new(texture.width as f32 * scale, texture.height as f32 * scale);
+        self.material_quad(material, size, position, [1.0; 4], z)
+    }
+
+    /// Draws `vertices` as a triangle list, with positions in pixels relative
+    /// to `position`.
+    pub fn material_mesh(
+        &mut self,
+        material: &mut Material,
+        vertices: &[MaterialVertex],
+        position: Vec2,
+        z: f32,
+    ) -> Result<(), MagmaError> {
+        let mvp = get_projection_matrix_z(Vec2::new(1.0, 1.0), position, z, self.projection_dimensions());
+        self.draw_material(material, vertices.to_vec(), false, mvp)
+    }
+
+    fn material_quad(
+        &mut self,
+        material: &mut Material,
+        size: Vec2,
+        position: Vec2,
+        color: [f32; 4],
+        z: f32,
+    ) -> Result<(), MagmaError> {
+        let vertices = [[-0.5, -0.5], [-0.5, 0.5], [0.5, -0.5], [0.5, 0.5]]
+            .iter()
+            .map(|&[x, y]| MaterialVertex::new([x, y], [x + 0.5, y + 0.5], color))
+            .collect();
+        let mvp = get_projection_matrix_z(size, position, z, self.projection_dimensions());
+        self.draw_material(material, vertices, true, mvp)
+    }
+
+    fn draw_material(
+        &mut self,
+        material: &mut Material,
+        vertices: Vec<MaterialVertex>,
+        strip: bool,
+        mvp: Mat4,
+    ) -> Result<(), MagmaError> {
+        let pipeline_name = format!("material:{}:{}", material.name, if strip { "strip" } else { "list" });
+        if !self.pipelines.contains_key(&pipeline_name) {
+            self.build_material_pipeline(&material.shader, &pipeline_name, strip)?;
+        }
+        let pipeline = self.pipelines.get(&pipeline_name).unwrap().clone();
+
+        let vertex_count = vertices.len();
+        let vertex_buffer = material.vertices.chunk(vertices)?;
+
+        let mut sets: Vec<Arc<dyn DescriptorSet + Send + Sync>> = Vec::new();
+        let has_uniforms = material.shader.uniform_block.is_some();
+        if has_uniforms || !material.textures.is_empty() {
+            let mut uniforms = material.uniforms.clone();
+            if let Some(member) = material.shader.uniform_block.as_ref().and_then(|block| {
+                block.members.iter().find(|m| m.name == MVP_UNIFORM && m.kind == UniformKind::Mat4)
+            }) {
+                UniformValue::from(mvp).write(&mut uniforms[member.offset ..]);
+            }
+            let textures = material
+                .textures
+                .iter()
+                .cloned()
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| MagmaError::Pipeline("a sampler of the material has no texture".to_string()))?;
+
+            let pool = self.frames[self.current_frame].descriptor_pool(&pipeline_name, &pipeline);
+            let t = &textures;
+            let set = if has_uniforms {
+                let buffer = material.uniform_buffers.chunk(uniforms)?;
+                match t.len() {
+                    0 => material_set!(pool.next().add_buffer(buffer)?;),
+                    1 => material_set!(pool.next().add_buffer(buffer)?; t[0]),
+                    2 => material_set!(pool.next().add_buffer(buffer)?; t[0], t[1]),
+                    3 => material_set!(pool.next().add_buffer(buffer)?; t[0], t[1], t[2]),
+                    _ => material_set!(pool.next().add_buffer(buffer)?; t[0], t[1], t[2], t[3]),
+                }
+            } else {
+                match t.len() {
+                    1 => material_set!(pool.next(); t[0]),
+                    2 => material_set!(pool.next(); t[0], t[1]),
+                    3 => material_set!(pool.next(); t[0], t[1], t[2]),
+                    _ => material_set!(pool.next(); t[0], t[1], t[2], t[3]),
+                }
+            };
+            sets.push(set);
+        }
+
+        let uploaded = vertex_count * size_of::<MaterialVertex>() + material.uniforms.len();
+        self.count_draw(&pipeline, vertex_count, sets.len(), uploaded, material.textures.len());
+        let mut cmb = self.take_command_buffer_builder()?;
+        let res = cmb.draw(pipeline, &self.dynamic_state, vertex_buffer, sets, ()).map(|_| ());
+        self.command_buffer_builder = Some(cmb);
+        res?;
+        Ok(())
+    }
+
+    fn build_material_pipeline(&mut self, shader: &Shader, name: &str, strip: bool) -> Result<(), MagmaError> {
+        let samplers = shader.samplers.len() as u32;
+        let has_uniforms = shader.uniform_block.is_some();
+
+        // Safe because the interfaces and layouts come from the modules'
+        // own SPIR-V.
+        let vs = unsafe {
+            shader.vertex.graphics_entry_point(
+                &shader.vertex_entry,
+                Interface(shader.vertex_reflection.inputs.clone()),
+                Interface(shader.vertex_reflection.outputs.clone()),
+                StageLayout::new(&shader.vertex_reflection, has_uniforms, samplers, ShaderStages {
+                    vertex: true,
+                    ..ShaderStages::none()
+                }),
+                GraphicsShaderType::Vertex,
+            )
+        };
+        let fs = unsafe {
+            shader.fragment.graphics_entry_point(
+                &shader.fragment_entry,
+                Interface(shader.fragment_reflection.inputs.clone()),
+                Interface(shader.fragment_reflection.outputs.clone()),
+                StageLayout::new(&shader.fragment_reflection, has_uniforms, samplers, ShaderStages {
+                    fragment: true,
+                    ..ShaderStages::none()
+                }),
+                GraphicsShaderType::Fragment,
+            )
+        };
+
+        let builder = GraphicsPipeline::start()
+            .vertex_input_single_buffer::<MaterialVertex>()
+            .vertex_shader(vs, ());
+        let builder = if strip { builder.triangle_strip() } else { builder.triangle_list() };
+        let builder = builder
+            .viewports_scissors_dynamic(1)
+            .fragment_shader(fs, ())
+            .blend_alpha_blending()
+            // Material output may be translucent, so it never writes depth.
+            .depth_stencil(self.depth_stencil(false))
+            .render_pass(Subpass::from(self.render_pass.clone(), 0).unwrap());
+        let pipeline = build_pipeline!(builder, self.device, self.pipeline_cache)?;
+        set_debug_name(&pipeline, name);
+
+        self.insert_pipeline(name.to_string(), Arc::new(pipeline));
+        Ok(())
+    }
+}
+
+
+/// Shader inputs or outputs found by reflection.
+#[derive(Debug, Clone)]
+struct Interface(Vec<InterfaceVariable>);
+
+unsafe impl ShaderInterfaceDef for Interface {
+    type Iter = std::vec::IntoIter<ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        self.0
+            .iter()
+            .map(|variable| ShaderInterfaceDefEntry {
+                location: variable.location .. variable.location + 1,
+                format: variable.format,
+                name: Some(Cow::Owned(variable.name.clone())),
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+/// Descriptors one stage uses out of the material's set 0.
+#[derive(Debug, Clone)]
+struct StageLayout {
+    bindings: Vec<Option<DescriptorDesc>>,
+}
+
+impl StageLayout {
+    fn new(reflection: &Reflection, has_uniforms: bool, samplers: u32, stages: ShaderStages) -> StageLayout {
+        let first_sampler = if has_uniforms { 1 } else { 0 };
+        let mut bindings = vec![None; (first_sampler + samplers) as usize];
+        if let Some(block) = &reflection.uniform_block {
+            bindings[block.binding as usize] = Some(DescriptorDesc {
+                ty: DescriptorDescTy::Buffer(DescriptorBufferDesc {
+                    dynamic: Some(false),
+                    storage: false,
+                }),
+                array_count: 1,
+                stages,
+                readonly: true,
+            });
+        }
+        for sampler in reflection.samplers.iter() {
+            bindings[sampler.binding as usize] = Some(DescriptorDesc {
+                ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+                    sampled: true,
+                    dimensions: DescriptorImageDescDimensions::TwoDimensional,
+                    format: None,
+                    multisampled: false,
+                    array_layers: DescriptorImageDescArray::NonArrayed,
+                }),
+                array_count: 1,
+                stages,
+                readonly: true,
+            });
+        }
+        StageLayout { bindings }
+    }
+}
+
+unsafe impl PipelineLayoutDesc for StageLayout {
+    fn num_sets(&self) -> usize {
+        if self.bindings.is_empty() { 0 } else { 1 }
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        if set == 0 && !self.bindings.is_empty() { Some(self.bindings.len()) } else { None }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        if set == 0 { self.bindings.get(binding).cloned().flatten() } else { None }
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        0
+    }
+
+    fn push_constants_range(&self, _num: usize) -> Option<PipelineLayoutDescPcRange> {
+        None
+    }
+}
@@ -0,0 +1,699 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::cpu_pool::CpuBufferPool;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor::{
+    DescriptorBufferDesc, DescriptorDesc, DescriptorDescTy, DescriptorImageDesc,
+    DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages,
+};
+use vulkano::descriptor::descriptor_set::FixedSizeDescriptorSetsPool;
+use vulkano::descriptor::pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::format::{ClearValue, Format};
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass};
+use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage, SwapchainImage};
+use vulkano::pipeline::shader::{GraphicsShaderType, ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule};
+use vulkano::pipeline::vertex::{BufferlessDefinition, BufferlessVertices};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use crate::cache::build_pipeline;
+use crate::debug::set_debug_name;
+use crate::error::MagmaError;
+use crate::SceneAttachments;
+
+/// Number of user values each pass receives in `params`.
+pub const POST_PARAMS: usize = 8;
+
+/// One fullscreen fragment-shader pass in `MamgaGfx::post_process`.
+///
+/// Every pass samples the output of the pass before it (the scene for the
+/// first one) and receives the framebuffer size, the time since the run loop
+/// started and eight user values in `params`.
+#[derive(Debug, Clone)]
+pub struct PostPass {
+    name: String,
+    shader: PassShader,
+    pub params: [f32; POST_PARAMS],
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone)]
+enum PassShader {
+    Scanlines,
+    Vignette,
+    Grayscale,
+    ChromaticAberration,
+    Blur,
+    Spirv(Arc<Vec<u8>>),
+}
+
+impl PostPass {
+    fn builtin(name: &str, shader: PassShader, params: &[f32]) -> PostPass {
+        let mut pass = PostPass {
+            name: name.to_string(),
+            shader,
+            params: [0.0; POST_PARAMS],
+            enabled: true,
+        };
+        pass.params[.. params.len()].copy_from_slice(params);
+        pass
+    }
+
+    /// CRT look. `params`: darkening of every other line (0.3), line
+    /// height in pixels (2.0), screen curvature (0.1).
+    pub fn crt_scanlines() -> PostPass {
+        PostPass::builtin("post_scanlines", PassShader::Scanlines, &[0.3, 2.0, 0.1])
+    }
+
+    /// Darkens the corners. `params`: strength (0.5), radius where darkening
+    /// starts (0.75), softness of the edge (0.45).
+    pub fn vignette() -> PostPass {
+        PostPass::builtin("post_vignette", PassShader::Vignette, &[0.5, 0.75, 0.45])
+    }
+
+    /// `params`: how far to desaturate, from 0.0 to 1.0 (1.0).
+    pub fn grayscale() -> PostPass {
+        PostPass::builtin("post_grayscale", PassShader::Grayscale, &[1.0])
+    }
+
+    /// Splits the red and blue channels outwards from the center. `params`:
+    /// offset at the edges in pixels (2.0).
+    pub fn chromatic_aberration() -> PostPass {
+        PostPass::builtin("post_chromatic_aberration", PassShader::ChromaticAberration, &[2.0])
+    }
+
+    /// 3x3 gaussian blur. `params`: distance between samples in pixels (1.0).
+    /// Repeat the pass for a wider blur.
+    pub fn blur() -> PostPass {
+        PostPass::builtin("post_blur", PassShader::Blur, &[1.0])
+    }
+
+    /// A pass running a SPIR-V fragment shader with this interface:
+    ///
+    /// ```glsl
+    /// layout(location = 0) in vec2 uv;
+    /// layout(location = 0) out vec4 f_color;
+    /// layout(set = 0, binding = 0) uniform sampler2D scene;
+    /// layout(set = 0, binding = 1) uniform Post {
+    ///     vec2 resolution;
+    ///     float time;
+    ///     vec4 params[2];
+    /// } post;
+    /// ```
+    ///
+    /// `name` identifies the pipeline, so every custom shader needs its own.
+    pub fn custom<N: Into<String>>(name: N, spirv: Vec<u8>) -> PostPass {
+        PostPass {
+            name: name.into(),
+            shader: PassShader::Spirv(Arc::new(spirv)),
+            params: [0.0; POST_PARAMS],
+            enabled: true,
+        }
+    }
+
+    pub fn with_param(mut self, index: usize, value: f32) -> Self {
+        self.params[index] = value;
+        self
+    }
+
+    pub fn with_params(mut self, params: [f32; POST_PARAMS]) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Uniform block shared by every pass, laid out to match `Post` in std140.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct PostUniforms {
+    resolution: [f32; 2],
+    time: f32,
+    _padding: f32,
+    params: [[f32; 4]; 2],
+}
+
+/// Ordered list of fullscreen passes run over the main window's frame
+/// before it is presented. Does nothing while no pass is enabled.
+pub struct PostProcess {
+    pub passes: Vec<PostPass>,
+    /// Seconds since the run loop started, handed to the passes.
+    pub(crate) time: f32,
+    /// Whether the frame being recorded draws into the scene image.
+    pub(crate) recording: bool,
+    resources: Option<PostResources>,
+    frames: Vec<PostFrame>,
+    outputs: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    dynamic_state: DynamicState,
+}
+
+/// Everything that survives a resize.
+struct PostResources {
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    vs: post_vs::Shader,
+    sampler: Arc<Sampler>,
+    uniforms: CpuBufferPool<PostUniforms>,
+    pipelines: HashMap<String, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    descriptor_pools: HashMap<String, FixedSizeDescriptorSetsPool>,
+    pipeline_cache: Arc<PipelineCache>,
+}
+
+/// Intermediate images for one frame in flight: the scene and two images
+/// the passes alternate between.
+struct PostFrame {
+    scene: Arc<AttachmentImage>,
+    scene_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    swap: [Arc<AttachmentImage>; 2],
+    swap_framebuffers: [Arc<dyn FramebufferAbstract + Send + Sync>; 2],
+}
+
+impl PostProcess {
+    pub(crate) fn new() -> Self {
+        PostProcess {
+            passes: Vec::new(),
+            time: 0.0,
+            recording: false,
+            resources: None,
+            frames: Vec::new(),
+            outputs: Vec::new(),
+            dynamic_state: DynamicState::none(),
+        }
+    }
+
+    pub fn push(&mut self, pass: PostPass) {
+        self.passes.push(pass);
+    }
+
+    pub fn clear(&mut self) {
+        self.passes.clear();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.passes.iter().any(|pass| pass.enabled)
+    }
+
+    /// Drops the size-dependent images, to be rebuilt by `prepare` for the
+    /// next frame.
+    pub(crate) fn invalidate(&mut self) {
+        self.frames.clear();
+        self.outputs.clear();
+    }
+
+    /// Makes sure the intermediate images exist for `images` and returns the
+    /// framebuffer the scene should be drawn into this frame.
+    pub(crate) fn prepare(
+        &mut self,
+        device: Arc<Device>,
+        pipeline_cache: Arc<PipelineCache>,
+        images: &[Arc<SwapchainImage<winit::window::Window>>],
+        scene_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        depth_buffer: bool,
+        samples: u32,
+        frames_in_flight: usize,
+        current_frame: usize,
+    ) -> Result<Arc<dyn FramebufferAbstract + Send + Sync>, MagmaError> {
+        let format = images[0].swapchain().format();
+        let dimensions = images[0].dimensions();
+        if self.resources.is_none() {
+            self.resources = Some(PostResources::new(device.clone(), pipeline_cache, format)?);
+        }
+        let render_pass = self.resources.as_ref().unwrap().render_pass.clone();
+
+        if self.frames.len() != frames_in_flight {
+            let intermediate = || -> Result<_, MagmaError> {
+                let image = AttachmentImage::with_usage(
+                    device.clone(),
+                    dimensions,
+                    format,
+                    ImageUsage {
+                        color_attachment: true,
+                        sampled: true,
+                        ..ImageUsage::none()
+                    },
+                )?;
+                set_debug_name(image.inner().image, "magma post process");
+                let framebuffer = Arc::new(
+                    Framebuffer::start(render_pass.clone())
+                        .add(image.clone())?
+                        .build()?,
+                ) as Arc<dyn FramebufferAbstract + Send + Sync>;
+                Ok((image, framebuffer))
+            };
+
+            self.frames = (0 .. frames_in_flight)
+                .map(|_| -> Result<_, MagmaError> {
+                    let scene = AttachmentImage::with_usage(
+                        device.clone(),
+                        dimensions,
+                        format,
+                        ImageUsage {
+                            color_attachment: true,
+                            sampled: true,
+                            ..ImageUsage::none()
+                        },
+                    )?;
+                    set_debug_name(scene.inner().image, "magma post process scene");
+                    // Frames overlap on the GPU, so each needs its own depth
+                    // and multisampled attachments as well.
+                    let attachments = SceneAttachments::new(device.clone(), dimensions, format, depth_buffer, samples)?;
+                    let scene_framebuffer = attachments.framebuffer(scene_pass.clone(), scene.clone())?;
+                    let (first, first_framebuffer) = intermediate()?;
+                    let (second, second_framebuffer) = intermediate()?;
+                    Ok(PostFrame {
+                        scene,
+                        scene_framebuffer,
+                        swap: [first, second],
+                        swap_framebuffers: [first_framebuffer, second_framebuffer],
+                    })
+                })
+                .collect::<Result<Vec<_>, MagmaError>>()?;
+            self.outputs = images
+                .iter()
+                .map(|image| -> Result<_, MagmaError> {
+                    Ok(Arc::new(
+                        Framebuffer::start(render_pass.clone())
+                            .add(image.clone())?
+                            .build()?,
+                    ) as Arc<dyn FramebufferAbstract + Send + Sync>)
+                })
+                .collect::<Result<Vec<_>, MagmaError>>()?;
+            self.dynamic_state = DynamicState {
+                viewports: Some(vec![Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                    depth_range: 0.0..1.0,
+                }]),
+                ..DynamicState::none()
+            };
+        }
+
+        self.recording = true;
+        Ok(self.frames[current_frame].scene_framebuffer.clone())
+    }
+
+    /// Records every enabled pass after the scene's render pass has ended,
+    /// with the last one writing into swapchain image `image_num`.
+    pub(crate) fn run(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder,
+        current_frame: usize,
+        image_num: usize,
+    ) -> Result<(), MagmaError> {
+        self.recording = false;
+        let resources = self.resources.as_mut().unwrap();
+        let frame = &self.frames[current_frame];
+        let dimensions = frame.scene.dimensions();
+        let passes: Vec<&PostPass> = self.passes.iter().filter(|pass| pass.enabled).collect();
+
+        let mut source = frame.scene.clone();
+        for (i, pass) in passes.iter().enumerate() {
+            let (framebuffer, output) = if i + 1 == passes.len() {
+                (self.outputs[image_num].clone(), None)
+            } else {
+                (frame.swap_framebuffers[i % 2].clone(), Some(frame.swap[i % 2].clone()))
+            };
+
+            let pipeline = resources.pipeline(pass)?;
+            let p = pass.params;
+            let uniforms = resources.uniforms.next(PostUniforms {
+                resolution: [dimensions[0] as f32, dimensions[1] as f32],
+                time: self.time,
+                _padding: 0.0,
+                params: [[p[0], p[1], p[2], p[3]], [p[4], p[5], p[6], p[7]]],
+            })?;
+            let sampler = resources.sampler.clone();
+            let set = resources
+                .descriptor_pools
+                .entry(pass.name.clone())
+                .or_insert_with(|| FixedSizeDescriptorSetsPool::new(pipeline.descriptor_set_layout(0).unwrap().clone()))
+                .next()
+                .add_sampled_image(source.clone(), sampler)?
+                .add_buffer(uniforms)?
+                .build()?;
+
+            builder
+                .begin_render_pass(framebuffer, false, vec![ClearValue::None])?
+                .draw(pipeline, &self.dynamic_state, BufferlessVertices { vertices: 3, instances: 1 }, set, ())?
+                .end_render_pass()?;
+            if let Some(output) = output {
+                source = output;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a pass pipeline from a fragment entry point. A macro because the
+/// builder's bounds differ for every shader type.
+macro_rules! pass_pipeline {
+    ($device:expr, $cache:expr, $render_pass:expr, $vs:expr, $fs:expr) => {
+        build_pipeline!(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader($vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader($fs, ())
+                .render_pass(Subpass::from($render_pass.clone(), 0).unwrap()),
+            $device,
+            $cache
+        )
+        .map(|pipeline| {
+            Arc::new(pipeline) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
+        })
+    };
+}
+
+impl PostResources {
+    fn new(
+        device: Arc<Device>,
+        pipeline_cache: Arc<PipelineCache>,
+        format: Format,
+    ) -> Result<PostResources, MagmaError> {
+        // Every pass overwrites the whole image, so nothing needs loading.
+        let render_pass = Arc::new(vulkano::single_pass_renderpass!(device.clone(),
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: format,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )?) as Arc<dyn RenderPassAbstract + Send + Sync>;
+
+        let sampler = Sampler::new(device.clone(), Filter::Linear, Filter::Linear,
+            MipmapMode::Nearest, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge, 0.0, 1.0, 0.0, 0.0)?;
+
+        Ok(PostResources {
+            render_pass,
+            vs: post_vs::Shader::load(device.clone())?,
+            sampler,
+            uniforms: CpuBufferPool::new(device, BufferUsage::uniform_buffer()),
+            pipelines: HashMap::new(),
+            descriptor_pools: HashMap::new(),
+            pipeline_cache,
+        })
+    }
+
+    fn pipeline(&mut self, pass: &PostPass) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, MagmaError> {
+        if let Some(pipeline) = self.pipelines.get(&pass.name) {
+            return Ok(pipeline.clone());
+        }
+
+        let device = self.render_pass.device().clone();
+        let render_pass = &self.render_pass;
+        let vs = &self.vs;
+        let pipeline = match &pass.shader {
+            PassShader::Scanlines => {
+                let fs = scanlines_fs::Shader::load(device.clone())?;
+                pass_pipeline!(device, self.pipeline_cache, render_pass, vs, fs.main_entry_point())?
+            }
+            PassShader::Vignette => {
+                let fs = vignette_fs::Shader::load(device.clone())?;
+                pass_pipeline!(device, self.pipeline_cache, render_pass, vs, fs.main_entry_point())?
+            }
+            PassShader::Grayscale => {
+                let fs = grayscale_fs::Shader::load(device.clone())?;
+                pass_pipeline!(device, self.pipeline_cache, render_pass, vs, fs.main_entry_point())?
+            }
+            PassShader::ChromaticAberration => {
+                let fs = chromatic_aberration_fs::Shader::load(device.clone())?;
+                pass_pipeline!(device, self.pipeline_cache, render_pass, vs, fs.main_entry_point())?
+            }
+            PassShader::Blur => {
+                let fs = blur_fs::Shader::load(device.clone())?;
+                pass_pipeline!(device, self.pipeline_cache, render_pass, vs, fs.main_entry_point())?
+            }
+            PassShader::Spirv(spirv) => {
+                // Safe as long as the shader sticks to the interface documented
+                // on `PostPass::custom`, which is all vulkano can't check.
+                let module = unsafe { ShaderModule::new(device.clone(), &spirv[..])? };
+                let fs = unsafe {
+                    module.graphics_entry_point(
+                        CStr::from_bytes_with_nul_unchecked(b"main\0"),
+                        CustomInput,
+                        CustomOutput,
+                        CustomLayout,
+                        GraphicsShaderType::Fragment,
+                    )
+                };
+                pass_pipeline!(device, self.pipeline_cache, render_pass, vs, fs)?
+            }
+        };
+        self.pipelines.insert(pass.name.clone(), pipeline.clone());
+        Ok(pipeline)
+    }
+}
+
+/// Interface of a custom pass's fragment shader, which vulkano can't derive
+/// from SPIR-V loaded at runtime.
+#[derive(Debug, Clone, Copy)]
+struct CustomInput;
+
+unsafe impl ShaderInterfaceDef for CustomInput {
+    type Iter = std::option::IntoIter<ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        Some(ShaderInterfaceDefEntry {
+            location: 0 .. 1,
+            format: Format::R32G32Sfloat,
+            name: Some(Cow::Borrowed("uv")),
+        })
+        .into_iter()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CustomOutput;
+
+unsafe impl ShaderInterfaceDef for CustomOutput {
+    type Iter = std::option::IntoIter<ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        Some(ShaderInterfaceDefEntry {
+            location: 0 .. 1,
+            format: Format::R32G32B32A32Sfloat,
+            name: Some(Cow::Borrowed("f_color")),
+        })
+        .into_iter()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CustomLayout;
+
+unsafe impl PipelineLayoutDesc for CustomLayout {
+    fn num_sets(&self) -> usize {
+        1
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        if set == 0 { Some(2) } else { None }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        let stages = ShaderStages {
+            fragment: true,
+            ..ShaderStages::none()
+        };
+        let ty = match (set, binding) {
+            (0, 0) => DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+                sampled: true,
+                dimensions: DescriptorImageDescDimensions::TwoDimensional,
+                format: None,
+                multisampled: false,
+                array_layers: DescriptorImageDescArray::NonArrayed,
+            }),
+            (0, 1) => DescriptorDescTy::Buffer(DescriptorBufferDesc {
+                dynamic: Some(false),
+                storage: false,
+            }),
+            _ => return None,
+        };
+        Some(DescriptorDesc {
+            ty,
+            array_count: 1,
+            stages,
+            readonly: true,
+        })
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        0
+    }
+
+    fn push_constants_range(&self, _num: usize) -> Option<PipelineLayoutDescPcRange> {
+        None
+    }
+}
+
+mod post_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+            #version 450
+
+            layout(location = 0) out vec2 uv;
+
+            void main() {
+                // A single triangle covering the screen, with uv running
+                // 0..1 across the visible part.
+                uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+            }
+        "
+    }
+}
+
+mod scanlines_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 f_color;
+            layout(set = 0, binding = 0) uniform sampler2D scene;
+            layout(set = 0, binding = 1) uniform Post {
+                vec2 resolution;
+                float time;
+                vec4 params[2];
+            } post;
+
+            void main() {
+                vec2 centered = uv * 2.0 - 1.0;
+                centered *= 1.0 + post.params[0].z * dot(centered.yx, centered.yx);
+                vec2 curved = centered * 0.5 + 0.5;
+                if (any(lessThan(curved, vec2(0.0))) || any(greaterThan(curved, vec2(1.0)))) {
+                    f_color = vec4(0.0, 0.0, 0.0, 1.0);
+                    return;
+                }
+                vec4 color = texture(scene, curved);
+                float line = floor(curved.y * post.resolution.y / max(post.params[0].y, 1.0));
+                float darken = mod(line, 2.0) * post.params[0].x;
+                f_color = vec4(color.rgb * (1.0 - darken), color.a);
+            }
+        "
+    }
+}
+
+mod vignette_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 f_color;
+            layout(set = 0, binding = 0) uniform sampler2D scene;
+            layout(set = 0, binding = 1) uniform Post {
+                vec2 resolution;
+                float time;
+                vec4 params[2];
+            } post;
+
+            void main() {
+                vec4 color = texture(scene, uv);
+                float dist = distance(uv, vec2(0.5)) * 1.41421356;
+                float shade = smoothstep(post.params[0].y, post.params[0].y - post.params[0].z, dist);
+                f_color = vec4(color.rgb * mix(1.0, shade, post.params[0].x), color.a);
+            }
+        "
+    }
+}
+
+mod grayscale_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 f_color;
+            layout(set = 0, binding = 0) uniform sampler2D scene;
+            layout(set = 0, binding = 1) uniform Post {
+                vec2 resolution;
+                float time;
+                vec4 params[2];
+            } post;
+
+            void main() {
+                vec4 color = texture(scene, uv);
+                float luma = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+                f_color = vec4(mix(color.rgb, vec3(luma), post.params[0].x), color.a);
+            }
+        "
+    }
+}
+
+mod chromatic_aberration_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 f_color;
+            layout(set = 0, binding = 0) uniform sampler2D scene;
+            layout(set = 0, binding = 1) uniform Post {
+                vec2 resolution;
+                float time;
+                vec4 params[2];
+            } post;
+
+            void main() {
+                vec2 offset = (uv - 0.5) * 2.0 * post.params[0].x / post.resolution;
+                vec4 color = texture(scene, uv);
+                float red = texture(scene, uv + offset).r;
+                float blue = texture(scene, uv - offset).b;
+                f_color = vec4(red, color.g, blue, color.a);
+            }
+        "
+    }
+}
+
+mod blur_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 f_color;
+            layout(set = 0, binding = 0) uniform sampler2D scene;
+            layout(set = 0, binding = 1) uniform Post {
+                vec2 resolution;
+                float time;
+                vec4 params[2];
+            } post;
+
+            void main() {
+                vec2 texel = post.params[0].x / post.resolution;
+                vec4 sum = vec4(0.0);
+                for (int x = -1; x <= 1; x++) {
+                    for (int y = -1; y <= 1; y++) {
+                        float weight = (2.0 - abs(float(x))) * (2.0 - abs(float(y)));
+                        sum += texture(scene, uv + vec2(x, y) * texel) * weight;
+                    }
+                }
+                f_color = sum / 16.0;
+            }
+        "
+    }
+}
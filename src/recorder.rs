@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use image::gif::GifEncoder;
+use image::{Delay, Frame, RgbaImage};
+
+use crate::error::MagmaError;
+use crate::screenshot::{PendingCapture, ReadbackBuffers};
+
+/// Where a recording is written.
+#[derive(Debug, Clone)]
+pub enum RecordingOutput {
+    /// Numbered `frame_00000.png` files in this directory, which is created if
+    /// it doesn't exist.
+    PngSequence(PathBuf),
+    /// A single animated GIF at this path.
+    Gif(PathBuf),
+}
+
+/// Settings for `MamgaGfx::start_recording`.
+///
+/// Frames are picked by elapsed time rather than by counting presented
+/// frames: the first frame presented in each `1 / fps` slot is captured, so
+/// a recording plays back at the speed it was rendered at whatever the frame
+/// rate.
+#[derive(Debug, Clone)]
+pub struct RecordingConfig {
+    pub output: RecordingOutput,
+    /// Frames captured per second of elapsed time, which is also the
+    /// playback rate. When rendering is slower, frames are repeated so the
+    /// recording still plays back in real time.
+    pub fps: u32,
+    /// Stop automatically once this much time has passed.
+    pub max_duration: Option<Duration>,
+}
+
+impl RecordingConfig {
+    pub fn png_sequence<P: Into<PathBuf>>(directory: P) -> Self {
+        RecordingConfig::new(RecordingOutput::PngSequence(directory.into()))
+    }
+
+    pub fn gif<P: Into<PathBuf>>(path: P) -> Self {
+        RecordingConfig::new(RecordingOutput::Gif(path.into()))
+    }
+
+    fn new(output: RecordingOutput) -> Self {
+        RecordingConfig {
+            output,
+            fps: 30,
+            max_duration: None,
+        }
+    }
+
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.fps = fps.max(1);
+        self
+    }
+
+    pub fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = Some(duration);
+        self
+    }
+}
+
+/// Captures presented frames and hands them to a worker thread for encoding.
+///
+/// Captures are read back only once the GPU has finished with them, so the
+/// render loop never waits on a recorded frame.
+pub(crate) struct Recorder {
+    config: RecordingConfig,
+    started: Instant,
+    /// Index of the `1 / fps` long slot of elapsed time last captured.
+    last_slot: Option<u64>,
+    pending: VecDeque<(PendingCapture, u64)>,
+    pub(crate) buffers: ReadbackBuffers,
+    sender: Option<Sender<(RgbaImage, u64)>>,
+    worker: Option<JoinHandle<Result<(), MagmaError>>>,
+}
+
+impl Recorder {
+    pub(crate) fn start(config: RecordingConfig, frames_in_flight: usize) -> Result<Recorder, MagmaError> {
+        let (sender, receiver) = channel::<(RgbaImage, u64)>();
+        let fps = config.fps;
+        let worker = match config.output.clone() {
+            RecordingOutput::PngSequence(directory) => {
+                fs::create_dir_all(&directory)?;
+                thread::spawn(move || -> Result<(), MagmaError> {
+                    let mut next = None;
+                    for (image, slot) in receiver.iter() {
+                        // Files are numbered by slot, so the sequence plays
+                        // back at `fps`, filling skipped slots with this frame.
+                        for i in covered_slots(next, slot) {
+                            image.save(directory.join(format!("frame_{:05}.png", i)))?;
+                        }
+                        next = Some(slot + 1);
+                    }
+                    Ok(())
+                })
+            }
+            RecordingOutput::Gif(path) => {
+                let file = File::create(path)?;
+                thread::spawn(move || -> Result<(), MagmaError> {
+                    let mut encoder = GifEncoder::new(BufWriter::new(file));
+                    let mut next = None;
+                    for (image, slot) in receiver.iter() {
+                        let delay = frame_delay(covered_slots(next, slot).count() as u32, fps);
+                        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+                        next = Some(slot + 1);
+                    }
+                    Ok(())
+                })
+            }
+        };
+
+        Ok(Recorder {
+            config,
+            started: Instant::now(),
+            last_slot: None,
+            pending: VecDeque::new(),
+            buffers: ReadbackBuffers::new(frames_in_flight),
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// The time slot to capture the frame about to be presented into, if it
+    /// starts a new one.
+    pub(crate) fn wants_frame(&mut self) -> Option<u64> {
+        let slot = new_slot(self.last_slot, self.started.elapsed(), &self.config)?;
+        self.last_slot = Some(slot);
+        Some(slot)
+    }
+
+    /// Whether `max_duration` has run out.
+    pub(crate) fn is_finished(&self) -> bool {
+        is_past_max(self.started.elapsed(), &self.config)
+    }
+
+    /// Queues a capture of a submitted frame for `poll`.
+    pub(crate) fn push(&mut self, capture: PendingCapture, slot: u64) {
+        self.pending.push_back((capture, slot));
+    }
+
+    /// Sends every capture the GPU has finished to the worker, in order.
+    pub(crate) fn poll(&mut self) -> Result<(), MagmaError> {
+        while let Some((capture, slot)) = self.pending.front() {
+            match capture.try_read()? {
+                Some(image) => {
+                    let slot = *slot;
+                    self.pending.pop_front();
+                    // A closed channel means the worker failed, which
+                    // `finish` reports.
+                    let _ = self.sender.as_ref().unwrap().send((image, slot));
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the remaining captures and waits for the worker to finish
+    /// writing. The GPU must be done with every pending capture.
+    pub(crate) fn finish(mut self) -> Result<(), MagmaError> {
+        self.poll()?;
+        if !self.pending.is_empty() {
+            return Err(MagmaError::BufferAllocation(
+                "recorded frames are still in use by the GPU".to_string(),
+            ));
+        }
+        drop(self.sender.take());
+        match self.worker.take().unwrap().join() {
+            Ok(result) => result,
+            Err(_) => Err(MagmaError::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "recording worker panicked",
+            ))),
+        }
+    }
+}
+
+fn is_past_max(elapsed: Duration, config: &RecordingConfig) -> bool {
+    config.max_duration.map_or(false, |max| elapsed >= max)
+}
+
+/// The slot `elapsed` falls in, if it comes after the `last` one captured and
+/// the recording hasn't run out.
+fn new_slot(last: Option<u64>, elapsed: Duration, config: &RecordingConfig) -> Option<u64> {
+    if is_past_max(elapsed, config) {
+        return None;
+    }
+    let slot = (elapsed.as_secs_f64() * config.fps as f64) as u64;
+    if last.map_or(false, |last| slot <= last) {
+        return None;
+    }
+    Some(slot)
+}
+
+/// Slots a frame captured in `slot` fills: its own, and any skipped since the
+/// previous capture when that left off before `next`.
+fn covered_slots(next: Option<u64>, slot: u64) -> RangeInclusive<u64> {
+    next.unwrap_or(slot) ..= slot
+}
+
+/// How long a GIF frame filling `slots` slots is shown for.
+fn frame_delay(slots: u32, fps: u32) -> Delay {
+    Delay::from_numer_denom_ms(1000 * slots, fps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn delay_ms(delay: Delay) -> f64 {
+        let (numer, denom) = delay.numer_denom_ms();
+        numer as f64 / denom as f64
+    }
+
+    #[test]
+    fn one_capture_per_slot() {
+        let config = RecordingConfig::gif("out.gif").with_fps(10);
+        assert_eq!(new_slot(None, ms(0), &config), Some(0));
+        assert_eq!(new_slot(Some(0), ms(50), &config), None);
+        assert_eq!(new_slot(Some(0), ms(100), &config), Some(1));
+        assert_eq!(new_slot(Some(1), ms(199), &config), None);
+    }
+
+    #[test]
+    fn slow_frames_skip_slots() {
+        let config = RecordingConfig::gif("out.gif").with_fps(10);
+        assert_eq!(new_slot(Some(1), ms(450), &config), Some(4));
+        // Slots 2 and 3 are filled by the frame captured in slot 4.
+        assert_eq!(covered_slots(Some(2), 4), 2 ..= 4);
+        assert_eq!(covered_slots(Some(5), 5), 5 ..= 5);
+        // The first capture only covers its own slot.
+        assert_eq!(covered_slots(None, 3), 3 ..= 3);
+    }
+
+    #[test]
+    fn capturing_stops_at_max_duration() {
+        let config = RecordingConfig::png_sequence("frames").with_fps(10).with_max_duration(ms(1000));
+        assert_eq!(new_slot(Some(8), ms(999), &config), Some(9));
+        assert_eq!(new_slot(Some(9), ms(1000), &config), None);
+        assert!(is_past_max(ms(1000), &config));
+        assert!(!is_past_max(ms(999), &config));
+        assert!(!is_past_max(ms(1_000_000), &RecordingConfig::png_sequence("frames")));
+    }
+
+    #[test]
+    fn gif_delay_spans_the_covered_slots() {
+        assert!((delay_ms(frame_delay(1, 10)) - 100.0).abs() < 1e-9);
+        assert!((delay_ms(frame_delay(3, 10)) - 300.0).abs() < 1e-9);
+        assert!((delay_ms(frame_delay(1, 30)) - 1000.0 / 30.0).abs() < 1e-9);
+        assert!((delay_ms(frame_delay(2, 60)) - 1000.0 / 30.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,522 @@
+//! Just enough SPIR-V parsing to find a shader's interface at runtime, which
+//! `vulkano_shaders::shader!` otherwise works out at build time.
+
+use std::collections::HashMap;
+
+use vulkano::format::Format;
+
+use crate::error::MagmaError;
+
+const MAGIC: u32 = 0x0723_0203;
+
+const OP_NAME: u16 = 5;
+const OP_ENTRY_POINT: u16 = 15;
+const OP_MEMBER_NAME: u16 = 6;
+const OP_TYPE_INT: u16 = 21;
+const OP_TYPE_FLOAT: u16 = 22;
+const OP_TYPE_VECTOR: u16 = 23;
+const OP_TYPE_MATRIX: u16 = 24;
+const OP_TYPE_SAMPLED_IMAGE: u16 = 27;
+const OP_TYPE_ARRAY: u16 = 28;
+const OP_TYPE_STRUCT: u16 = 30;
+const OP_TYPE_POINTER: u16 = 32;
+const OP_CONSTANT: u16 = 43;
+const OP_VARIABLE: u16 = 59;
+const OP_DECORATE: u16 = 71;
+const OP_MEMBER_DECORATE: u16 = 72;
+
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+pub(crate) const EXECUTION_MODEL_VERTEX: u32 = 0;
+pub(crate) const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+
+/// Type of a uniform block member that materials know how to fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniformKind {
+    Float,
+    Int,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat4,
+}
+
+impl UniformKind {
+    pub(crate) fn size(self) -> usize {
+        match self {
+            UniformKind::Float | UniformKind::Int => 4,
+            UniformKind::Vec2 => 8,
+            UniformKind::Vec3 => 12,
+            UniformKind::Vec4 => 16,
+            UniformKind::Mat4 => 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct UniformMember {
+    pub(crate) name: String,
+    pub(crate) offset: usize,
+    pub(crate) kind: UniformKind,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct UniformBlock {
+    pub(crate) binding: u32,
+    pub(crate) size: usize,
+    pub(crate) members: Vec<UniformMember>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SamplerBinding {
+    pub(crate) binding: u32,
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct InterfaceVariable {
+    pub(crate) location: u32,
+    pub(crate) format: Format,
+    pub(crate) name: String,
+}
+
+/// Everything magma-gfx needs to know about one shader stage.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Reflection {
+    /// Execution model and name of each entry point.
+    pub(crate) entry_points: Vec<(u32, String)>,
+    pub(crate) inputs: Vec<InterfaceVariable>,
+    pub(crate) outputs: Vec<InterfaceVariable>,
+    pub(crate) uniform_block: Option<UniformBlock>,
+    pub(crate) samplers: Vec<SamplerBinding>,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Int,
+    Float,
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    SampledImage,
+    /// Holds the id of the constant with the array's length.
+    Array(u32),
+    Struct(Vec<u32>),
+    Pointer(u32),
+}
+
+fn invalid(message: &str) -> MagmaError {
+    MagmaError::Pipeline(format!("can't reflect shader: {}", message))
+}
+
+fn decode_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Reads the inputs, outputs, uniform block and samplers of the SPIR-V
+/// module in `spirv`. Only descriptor set 0 is supported.
+pub(crate) fn reflect(spirv: &[u8]) -> Result<Reflection, MagmaError> {
+    if spirv.len() % 4 != 0 || spirv.len() < 20 {
+        return Err(invalid("not a SPIR-V module"));
+    }
+    let words: Vec<u32> = spirv
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    if words[0] != MAGIC {
+        return Err(invalid("not a little-endian SPIR-V module"));
+    }
+
+    let mut names: HashMap<u32, String> = HashMap::new();
+    let mut member_names: HashMap<(u32, u32), String> = HashMap::new();
+    let mut decorations: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut member_offsets: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut types: HashMap<u32, Type> = HashMap::new();
+    let mut constants: HashMap<u32, u32> = HashMap::new();
+    let mut variables: Vec<(u32, u32, u32)> = Vec::new();
+    let mut entry_points: Vec<(u32, String)> = Vec::new();
+
+    let mut i = 5;
+    while i < words.len() {
+        let count = (words[i] >> 16) as usize;
+        let opcode = (words[i] & 0xffff) as u16;
+        if count == 0 || i + count > words.len() {
+            return Err(invalid("truncated instruction"));
+        }
+        let ops = &words[i + 1 .. i + count];
+        match opcode {
+            OP_ENTRY_POINT if ops.len() >= 3 => {
+                entry_points.push((ops[0], decode_string(&ops[2 ..])));
+            }
+            OP_NAME => {
+                names.insert(ops[0], decode_string(&ops[1 ..]));
+            }
+            OP_MEMBER_NAME => {
+                member_names.insert((ops[0], ops[1]), decode_string(&ops[2 ..]));
+            }
+            OP_DECORATE if ops.len() >= 3 => {
+                decorations.insert((ops[0], ops[1]), ops[2]);
+            }
+            OP_MEMBER_DECORATE if ops.len() >= 4 && ops[2] == DECORATION_OFFSET => {
+                member_offsets.insert((ops[0], ops[1]), ops[3]);
+            }
+            OP_TYPE_INT => {
+                types.insert(ops[0], Type::Int);
+            }
+            OP_TYPE_FLOAT => {
+                types.insert(ops[0], Type::Float);
+            }
+            OP_TYPE_VECTOR => {
+                types.insert(ops[0], Type::Vector(ops[1], ops[2]));
+            }
+            OP_TYPE_MATRIX => {
+                types.insert(ops[0], Type::Matrix(ops[1], ops[2]));
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                types.insert(ops[0], Type::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                types.insert(ops[0], Type::Array(ops[2]));
+            }
+            OP_TYPE_STRUCT => {
+                types.insert(ops[0], Type::Struct(ops[1 ..].to_vec()));
+            }
+            OP_TYPE_POINTER => {
+                types.insert(ops[0], Type::Pointer(ops[2]));
+            }
+            OP_CONSTANT if ops.len() >= 3 => {
+                constants.insert(ops[1], ops[2]);
+            }
+            OP_VARIABLE => {
+                variables.push((ops[0], ops[1], ops[2]));
+            }
+            _ => {}
+        }
+        i += count;
+    }
+
+    let pointee = |type_id: u32| match types.get(&type_id) {
+        Some(Type::Pointer(inner)) => Some(*inner),
+        _ => None,
+    };
+    let name_of = |id: u32| names.get(&id).cloned().unwrap_or_default();
+
+    let mut reflection = Reflection {
+        entry_points,
+        ..Reflection::default()
+    };
+    for &(type_id, id, storage) in variables.iter() {
+        let inner = pointee(type_id).ok_or_else(|| invalid("variable isn't a pointer"))?;
+        match storage {
+            STORAGE_INPUT | STORAGE_OUTPUT => {
+                // Built-ins like gl_Position have no location.
+                let location = match decorations.get(&(id, DECORATION_LOCATION)) {
+                    Some(&location) => location,
+                    None => continue,
+                };
+                let variable = InterfaceVariable {
+                    location,
+                    format: interface_format(&types, inner)?,
+                    name: name_of(id),
+                };
+                if storage == STORAGE_INPUT {
+                    reflection.inputs.push(variable);
+                } else {
+                    reflection.outputs.push(variable);
+                }
+            }
+            STORAGE_UNIFORM | STORAGE_UNIFORM_CONSTANT => {
+                let set = decorations.get(&(id, DECORATION_DESCRIPTOR_SET)).cloned().unwrap_or(0);
+                let binding = decorations.get(&(id, DECORATION_BINDING)).cloned().unwrap_or(0);
+                if set != 0 {
+                    return Err(invalid("only descriptor set 0 is supported"));
+                }
+                match types.get(&inner) {
+                    Some(Type::SampledImage) => reflection.samplers.push(SamplerBinding {
+                        binding,
+                        name: name_of(id),
+                    }),
+                    Some(Type::Struct(members)) => {
+                        if reflection.uniform_block.is_some() {
+                            return Err(invalid("only one uniform block is supported"));
+                        }
+                        let mut block = UniformBlock {
+                            binding,
+                            size: 0,
+                            members: Vec::new(),
+                        };
+                        for (index, &member_type) in members.iter().enumerate() {
+                            let index = index as u32;
+                            let offset = *member_offsets
+                                .get(&(inner, index))
+                                .ok_or_else(|| invalid("uniform member has no offset"))? as usize;
+                            let name = member_names.get(&(inner, index)).cloned().unwrap_or_default();
+                            let size = match uniform_kind(&types, member_type) {
+                                Some(kind) => {
+                                    block.members.push(UniformMember { name, offset, kind });
+                                    kind.size()
+                                }
+                                // Members of other types are left zeroed.
+                                None => type_size(&types, &constants, &decorations, member_type),
+                            };
+                            block.size = block.size.max(offset + size);
+                        }
+                        // Uniform buffers are read in 16 byte units.
+                        block.size = (block.size + 15) / 16 * 16;
+                        reflection.uniform_block = Some(block);
+                    }
+                    _ => return Err(invalid("only uniform blocks and sampler2Ds are supported")),
+                }
+            }
+            _ => {}
+        }
+    }
+    reflection.inputs.sort_by_key(|variable| variable.location);
+    reflection.outputs.sort_by_key(|variable| variable.location);
+    reflection.samplers.sort_by_key(|sampler| sampler.binding);
+    Ok(reflection)
+}
+
+impl Reflection {
+    /// Name of the first entry point with `execution_model`.
+    pub(crate) fn entry_point(&self, execution_model: u32) -> Option<&str> {
+        self.entry_points
+            .iter()
+            .find(|(model, _)| *model == execution_model)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+fn interface_format(types: &HashMap<u32, Type>, type_id: u32) -> Result<Format, MagmaError> {
+    match types.get(&type_id) {
+        Some(Type::Float) => Ok(Format::R32Sfloat),
+        Some(Type::Int) => Ok(Format::R32Sint),
+        Some(Type::Vector(component, count)) => {
+            let float = match types.get(component) {
+                Some(Type::Float) => true,
+                Some(Type::Int) => false,
+                _ => return Err(invalid("unsupported vector component type")),
+            };
+            Ok(match (float, count) {
+                (true, 2) => Format::R32G32Sfloat,
+                (true, 3) => Format::R32G32B32Sfloat,
+                (true, 4) => Format::R32G32B32A32Sfloat,
+                (false, 2) => Format::R32G32Sint,
+                (false, 3) => Format::R32G32B32Sint,
+                (false, 4) => Format::R32G32B32A32Sint,
+                _ => return Err(invalid("unsupported vector size")),
+            })
+        }
+        _ => Err(invalid("shader inputs and outputs must be scalars or vectors")),
+    }
+}
+
+fn uniform_kind(types: &HashMap<u32, Type>, type_id: u32) -> Option<UniformKind> {
+    match types.get(&type_id)? {
+        Type::Float => Some(UniformKind::Float),
+        Type::Int => Some(UniformKind::Int),
+        Type::Vector(component, count) => match (types.get(component)?, count) {
+            (Type::Float, 2) => Some(UniformKind::Vec2),
+            (Type::Float, 3) => Some(UniformKind::Vec3),
+            (Type::Float, 4) => Some(UniformKind::Vec4),
+            _ => None,
+        },
+        Type::Matrix(column, 4) => match types.get(column)? {
+            Type::Vector(_, 4) => Some(UniformKind::Mat4),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Size in bytes of a uniform member type under std140, using the array
+/// stride the compiler decorated arrays with.
+fn type_size(
+    types: &HashMap<u32, Type>,
+    constants: &HashMap<u32, u32>,
+    decorations: &HashMap<(u32, u32), u32>,
+    type_id: u32,
+) -> usize {
+    match types.get(&type_id) {
+        Some(Type::Float) | Some(Type::Int) => 4,
+        Some(Type::Vector(_, count)) => 4 * *count as usize,
+        // Columns are padded to a vec4 each.
+        Some(Type::Matrix(_, columns)) => 16 * *columns as usize,
+        Some(Type::Array(length_id)) => {
+            let stride = decorations.get(&(type_id, DECORATION_ARRAY_STRIDE)).cloned().unwrap_or(16);
+            let length = constants.get(length_id).cloned().unwrap_or(0);
+            stride as usize * length as usize
+        }
+        Some(Type::Struct(members)) => members
+            .iter()
+            .map(|&member| type_size(types, constants, decorations, member))
+            .sum(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str, kind: shaderc::ShaderKind) -> Vec<u8> {
+        let mut compiler = shaderc::Compiler::new().unwrap();
+        compiler
+            .compile_into_spirv(source, kind, "fixture.glsl", "main", None)
+            .unwrap()
+            .as_binary_u8()
+            .to_vec()
+    }
+
+    const VERTEX: &str = "
+        #version 450
+
+        layout(location = 0) in vec2 position;
+        layout(location = 1) in vec2 uv;
+        layout(location = 2) in vec4 color;
+        layout(location = 0) out vec2 v_uv;
+        layout(location = 1) out vec4 v_color;
+
+        layout(set = 0, binding = 0) uniform Data {
+            mat4 mvp;
+            float time;
+            vec3 tint;
+            vec2 offset;
+            int mode;
+        } uniforms;
+
+        void main() {
+            gl_Position = uniforms.mvp * vec4(position + uniforms.offset * uniforms.time, 0.0, 1.0);
+            v_uv = uv;
+            v_color = color * vec4(uniforms.tint, float(uniforms.mode));
+        }
+    ";
+
+    const FRAGMENT: &str = "
+        #version 450
+
+        layout(location = 0) in vec2 v_uv;
+        layout(location = 1) in vec4 v_color;
+        layout(location = 0) out vec4 f_color;
+
+        layout(set = 0, binding = 1) uniform sampler2D base;
+        layout(set = 0, binding = 2) uniform sampler2D mask;
+
+        void main() {
+            f_color = texture(base, v_uv) * texture(mask, v_uv).r * v_color;
+        }
+    ";
+
+    fn member(block: &UniformBlock, name: &str) -> (usize, UniformKind) {
+        let member = block.members.iter().find(|member| member.name == name).unwrap();
+        (member.offset, member.kind)
+    }
+
+    #[test]
+    fn uniform_members_have_std140_offsets() {
+        let reflection = reflect(&compile(VERTEX, shaderc::ShaderKind::Vertex)).unwrap();
+        let block = reflection.uniform_block.unwrap();
+        assert_eq!(block.binding, 0);
+        assert_eq!(member(&block, "mvp"), (0, UniformKind::Mat4));
+        assert_eq!(member(&block, "time"), (64, UniformKind::Float));
+        assert_eq!(member(&block, "tint"), (80, UniformKind::Vec3));
+        assert_eq!(member(&block, "offset"), (96, UniformKind::Vec2));
+        assert_eq!(member(&block, "mode"), (104, UniformKind::Int));
+        assert_eq!(block.size, 112);
+    }
+
+    #[test]
+    fn unsupported_members_still_count_towards_the_size() {
+        let source = "
+            #version 450
+            layout(location = 0) out vec4 f_color;
+            layout(set = 0, binding = 0) uniform Data {
+                vec4 a;
+                float weights[3];
+                vec2 b;
+            } uniforms;
+            void main() {
+                f_color = uniforms.a * uniforms.weights[2] + vec4(uniforms.b, 0.0, 0.0);
+            }
+        ";
+        let reflection = reflect(&compile(source, shaderc::ShaderKind::Fragment)).unwrap();
+        let block = reflection.uniform_block.unwrap();
+        assert_eq!(block.members.len(), 2);
+        assert_eq!(member(&block, "a"), (0, UniformKind::Vec4));
+        assert_eq!(member(&block, "b"), (64, UniformKind::Vec2));
+        assert_eq!(block.size, 80);
+    }
+
+    #[test]
+    fn samplers_are_found_in_binding_order() {
+        let reflection = reflect(&compile(FRAGMENT, shaderc::ShaderKind::Fragment)).unwrap();
+        assert!(reflection.uniform_block.is_none());
+        let samplers: Vec<(u32, &str)> = reflection
+            .samplers
+            .iter()
+            .map(|sampler| (sampler.binding, sampler.name.as_str()))
+            .collect();
+        assert_eq!(samplers, vec![(1, "base"), (2, "mask")]);
+    }
+
+    #[test]
+    fn interface_variables_have_locations_and_formats() {
+        let reflection = reflect(&compile(VERTEX, shaderc::ShaderKind::Vertex)).unwrap();
+        let inputs: Vec<(u32, Format, &str)> = reflection
+            .inputs
+            .iter()
+            .map(|variable| (variable.location, variable.format, variable.name.as_str()))
+            .collect();
+        assert_eq!(inputs, vec![
+            (0, Format::R32G32Sfloat, "position"),
+            (1, Format::R32G32Sfloat, "uv"),
+            (2, Format::R32G32B32A32Sfloat, "color"),
+        ]);
+        // gl_Position has no location and is left out.
+        let outputs: Vec<(u32, Format)> = reflection
+            .outputs
+            .iter()
+            .map(|variable| (variable.location, variable.format))
+            .collect();
+        assert_eq!(outputs, vec![(0, Format::R32G32Sfloat), (1, Format::R32G32B32A32Sfloat)]);
+    }
+
+    #[test]
+    fn entry_points_are_read() {
+        let vertex = reflect(&compile(VERTEX, shaderc::ShaderKind::Vertex)).unwrap();
+        assert_eq!(vertex.entry_point(EXECUTION_MODEL_VERTEX), Some("main"));
+        assert_eq!(vertex.entry_point(EXECUTION_MODEL_FRAGMENT), None);
+    }
+
+    #[test]
+    fn other_descriptor_sets_are_rejected() {
+        let source = "
+            #version 450
+            layout(location = 0) out vec4 f_color;
+            layout(set = 1, binding = 0) uniform sampler2D tex;
+            void main() {
+                f_color = texture(tex, vec2(0.5));
+            }
+        ";
+        assert!(reflect(&compile(source, shaderc::ShaderKind::Fragment)).is_err());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(reflect(&[0; 32]).is_err());
+        assert!(reflect(&[1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use glam::Vec2;
+use vulkano::pipeline::viewport::Viewport;
+
+use crate::core::{Color, MamgaGfx, Renderer};
+use crate::error::MagmaError;
+use crate::shapes::Rectangle;
+
+/// How a `VirtualResolution` is scaled up to the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalingMode {
+    /// The largest whole multiple that fits, so every virtual pixel covers
+    /// the same number of window pixels. Never scales below 1.
+    Integer,
+    /// The largest scale that fits, keeping the aspect ratio.
+    Fit,
+    /// The smallest scale that covers the window, cropping the overflow.
+    Fill,
+}
+
+/// A fixed drawing size, e.g. 320x180 for pixel art, that stays the same
+/// however the window is resized.
+///
+/// Draw coordinates cover `width` by `height` and the scene is scaled into
+/// the middle of the window, with `letterbox_color` in the leftover space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualResolution {
+    pub width: u32,
+    pub height: u32,
+    pub scaling: ScalingMode,
+    pub letterbox_color: [f32; 4],
+}
+
+impl VirtualResolution {
+    pub fn new(width: u32, height: u32) -> Self {
+        VirtualResolution {
+            width,
+            height,
+            scaling: ScalingMode::Integer,
+            letterbox_color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    pub fn with_scaling(mut self, scaling: ScalingMode) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    pub fn with_letterbox_color(mut self, color: Color) -> Self {
+        self.letterbox_color = color.normalise();
+        self
+    }
+
+    /// Where the virtual screen ends up in a window of `window` pixels.
+    pub fn placement(&self, window: [u32; 2]) -> VirtualPlacement {
+        let scale_x = window[0] as f32 / self.width as f32;
+        let scale_y = window[1] as f32 / self.height as f32;
+        let scale = match self.scaling {
+            ScalingMode::Integer => scale_x.min(scale_y).floor().max(1.0),
+            ScalingMode::Fit => scale_x.min(scale_y),
+            ScalingMode::Fill => scale_x.max(scale_y),
+        };
+        let size = Vec2::new(self.width as f32 * scale, self.height as f32 * scale);
+        // Whole pixels, so integer scaling lines up with the window's grid.
+        let origin = Vec2::new(
+            ((window[0] as f32 - size.x()) / 2.0).floor(),
+            ((window[1] as f32 - size.y()) / 2.0).floor(),
+        );
+        VirtualPlacement { origin, size, scale }
+    }
+}
+
+/// The rectangle a `VirtualResolution` is drawn into, in window pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualPlacement {
+    pub origin: Vec2,
+    pub size: Vec2,
+    /// Window pixels per virtual pixel.
+    pub scale: f32,
+}
+
+impl VirtualPlacement {
+    /// Maps a window position, such as the cursor's, into virtual
+    /// coordinates. Returns `None` over the letterbox bars.
+    pub fn to_virtual(&self, position: Vec2) -> Option<Vec2> {
+        let local = position - self.origin;
+        if local.x() < 0.0 || local.y() < 0.0 || local.x() >= self.size.x() || local.y() >= self.size.y() {
+            return None;
+        }
+        Some(local / self.scale)
+    }
+
+    pub(crate) fn viewport(&self) -> Viewport {
+        Viewport {
+            origin: [self.origin.x(), self.origin.y()],
+            dimensions: [self.size.x(), self.size.y()],
+            depth_range: 0.0..1.0,
+        }
+    }
+}
+
+impl Renderer {
+    pub fn virtual_resolution(&self) -> Option<VirtualResolution> {
+        self.virtual_resolution
+    }
+
+    /// What the whole framebuffer is cleared to: `clear_color`, or the
+    /// letterbox color with a virtual resolution.
+    pub(crate) fn framebuffer_clear_color(&self, clear_color: [f32; 4]) -> [f32; 4] {
+        self.output_rgba(match self.virtual_resolution {
+            Some(resolution) => resolution.letterbox_color,
+            None => clear_color,
+        })
+    }
+
+    /// Paints the virtual screen `color` at the back of the scene. The
+    /// framebuffer itself is cleared to the letterbox color.
+    pub(crate) fn clear_virtual_screen(&mut self, color: [f32; 4]) -> Result<(), MagmaError> {
+        if let Some(resolution) = self.virtual_resolution {
+            let size = Vec2::new(resolution.width as f32, resolution.height as f32);
+            let rectangle = Rectangle {
+                position: size / 2.0,
+                width: resolution.width as i32,
+                height: resolution.height as i32,
+            };
+            let color = self.output_rgba(color);
+            self.rectangle_rgba(&rectangle, color, -1.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl MamgaGfx {
+    /// Switches virtual resolution on, off or to another size. Takes effect
+    /// from the next frame.
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>) {
+        self.config.virtual_resolution = resolution;
+        self.renderer.virtual_resolution = resolution;
+        self.output.recreate_swapchain = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec2, b: Vec2) -> bool {
+        (a - b).length() < 1e-3
+    }
+
+    #[test]
+    fn integer_scaling_uses_whole_multiples() {
+        let resolution = VirtualResolution::new(320, 180);
+        let placement = resolution.placement([1280, 720]);
+        assert_eq!(placement.scale, 4.0);
+        assert_eq!(placement.origin, Vec2::new(0.0, 0.0));
+        assert_eq!(placement.size, Vec2::new(1280.0, 720.0));
+
+        let placement = resolution.placement([1000, 600]);
+        assert_eq!(placement.scale, 3.0);
+        assert_eq!(placement.origin, Vec2::new(20.0, 30.0));
+        assert_eq!(placement.size, Vec2::new(960.0, 540.0));
+    }
+
+    #[test]
+    fn integer_scaling_never_goes_below_one() {
+        let placement = VirtualResolution::new(320, 180).placement([200, 100]);
+        assert_eq!(placement.scale, 1.0);
+        assert_eq!(placement.origin, Vec2::new(-60.0, -40.0));
+    }
+
+    #[test]
+    fn fit_letterboxes_the_short_side() {
+        let placement = VirtualResolution::new(320, 180)
+            .with_scaling(ScalingMode::Fit)
+            .placement([1000, 600]);
+        assert_eq!(placement.scale, 3.125);
+        assert_eq!(placement.size, Vec2::new(1000.0, 562.5));
+        assert_eq!(placement.origin, Vec2::new(0.0, 18.0));
+    }
+
+    #[test]
+    fn fill_crops_the_long_side() {
+        let placement = VirtualResolution::new(320, 180)
+            .with_scaling(ScalingMode::Fill)
+            .placement([1000, 600]);
+        assert!((placement.scale - 600.0 / 180.0).abs() < 1e-4);
+        assert!(close(placement.size, Vec2::new(1066.667, 600.0)));
+        assert_eq!(placement.origin, Vec2::new(-34.0, 0.0));
+    }
+
+    #[test]
+    fn to_virtual_maps_inside_and_rejects_the_bars() {
+        let placement = VirtualResolution::new(320, 180).placement([1000, 600]);
+        assert_eq!(placement.to_virtual(Vec2::new(20.0, 30.0)), Some(Vec2::new(0.0, 0.0)));
+        assert_eq!(placement.to_virtual(Vec2::new(500.0, 300.0)), Some(Vec2::new(160.0, 90.0)));
+        assert!(close(placement.to_virtual(Vec2::new(979.0, 569.0)).unwrap(), Vec2::new(319.667, 179.667)));
+        assert_eq!(placement.to_virtual(Vec2::new(19.0, 100.0)), None);
+        assert_eq!(placement.to_virtual(Vec2::new(980.0, 100.0)), None);
+        assert_eq!(placement.to_virtual(Vec2::new(100.0, 570.0)), None);
+    }
+
+    #[test]
+    fn to_virtual_with_fill_maps_the_window_edge_inside() {
+        let placement = VirtualResolution::new(320, 180)
+            .with_scaling(ScalingMode::Fill)
+            .placement([1000, 600]);
+        let corner = placement.to_virtual(Vec2::new(0.0, 0.0)).unwrap();
+        assert!(close(corner, Vec2::new(10.2, 0.0)));
+    }
+}
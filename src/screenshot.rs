@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+use image::RgbaImage;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer};
+use vulkano::buffer::cpu_access::ReadLockError;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::ImageAccess;
+
+use crate::debug::set_debug_name;
+use crate::error::MagmaError;
+
+/// What to do with a captured frame once it reaches the CPU.
+#[derive(Debug, Clone)]
+pub(crate) enum CaptureTarget {
+    /// Keep it for `MamgaGfx::take_screenshot`.
+    Keep,
+    Save(PathBuf),
+}
+
+/// Writes screenshots on a worker thread, so encoding never holds up a
+/// frame. Dropping it waits for everything queued to be written.
+pub(crate) struct ScreenshotWriter {
+    sender: Option<Sender<(RgbaImage, PathBuf)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ScreenshotWriter {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = channel::<(RgbaImage, PathBuf)>();
+        let worker = thread::spawn(move || {
+            // There's no frame left to return an error from by now.
+            for (image, path) in receiver.iter() {
+                if let Err(e) = image.save(&path) {
+                    log::error!("failed to save screenshot {}: {}", path.display(), e);
+                }
+            }
+        });
+        ScreenshotWriter {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    pub(crate) fn save(&self, image: RgbaImage, path: PathBuf) {
+        // The worker only stops once the sender is dropped.
+        let _ = self.sender.as_ref().unwrap().send((image, path));
+    }
+}
+
+impl Drop for ScreenshotWriter {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A frame copied into a host-visible buffer that the GPU may still be
+/// writing to.
+pub(crate) struct PendingCapture {
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    dimensions: [u32; 2],
+    format: Format,
+}
+
+impl PendingCapture {
+    /// Records a copy of `image` into `buffer` at the end of `builder`, which
+    /// must be outside a render pass.
+    pub(crate) fn record<I>(
+        builder: &mut AutoCommandBufferBuilder,
+        image: I,
+        buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+        dimensions: [u32; 2],
+        format: Format,
+    ) -> Result<PendingCapture, MagmaError>
+    where
+        I: ImageAccess + Send + Sync + 'static,
+    {
+        bytes_per_pixel(format)?;
+        builder.copy_image_to_buffer(image, buffer.clone())?;
+        Ok(PendingCapture { buffer, dimensions, format })
+    }
+
+    /// Converts the capture to RGBA, or returns `None` if the GPU hasn't
+    /// finished the frame yet.
+    pub(crate) fn try_read(&self) -> Result<Option<RgbaImage>, MagmaError> {
+        let bytes = match self.buffer.read() {
+            Ok(bytes) => bytes,
+            Err(ReadLockError::GpuWriteLocked) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        to_rgba(&bytes, self.dimensions, self.format).map(Some)
+    }
+}
+
+/// A host-visible buffer big enough for a `dimensions` sized capture. It's
+/// left uninitialised, since the copy overwrites all of it.
+pub(crate) fn readback_buffer(device: Arc<Device>, dimensions: [u32; 2]) -> Result<Arc<CpuAccessibleBuffer<[u8]>>, MagmaError> {
+    let len = (dimensions[0] * dimensions[1] * 4) as usize;
+    // Safe because nothing reads the buffer before a copy has filled it.
+    let buffer = unsafe {
+        CpuAccessibleBuffer::uninitialized_array(device, len, BufferUsage::transfer_destination(), true)?
+    };
+    set_debug_name(buffer.inner().buffer, "magma capture readback");
+    Ok(buffer)
+}
+
+/// One readback buffer per frame in flight, reused for every capture made
+/// in that frame.
+pub(crate) struct ReadbackBuffers {
+    buffers: Vec<Option<Arc<CpuAccessibleBuffer<[u8]>>>>,
+}
+
+impl ReadbackBuffers {
+    pub(crate) fn new(frames_in_flight: usize) -> Self {
+        ReadbackBuffers {
+            buffers: vec![None; frames_in_flight.max(1)],
+        }
+    }
+
+    /// The buffer for `frame`, replaced when the size changed or a capture
+    /// still hasn't been read out of it.
+    pub(crate) fn get(
+        &mut self,
+        device: Arc<Device>,
+        frame: usize,
+        dimensions: [u32; 2],
+    ) -> Result<Arc<CpuAccessibleBuffer<[u8]>>, MagmaError> {
+        let len = (dimensions[0] * dimensions[1] * 4) as usize;
+        let slot = &mut self.buffers[frame % self.buffers.len()];
+        match slot {
+            Some(buffer) if buffer.size() == len && Arc::strong_count(buffer) == 1 => Ok(buffer.clone()),
+            _ => {
+                let buffer = readback_buffer(device, dimensions)?;
+                *slot = Some(buffer.clone());
+                Ok(buffer)
+            }
+        }
+    }
+}
+
+fn bytes_per_pixel(format: Format) -> Result<usize, MagmaError> {
+    match format {
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb | Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => Ok(4),
+        format => Err(MagmaError::Swapchain(format!("can't capture images in {:?}", format))),
+    }
+}
+
+/// Reorders pixels in `format` into RGBA8.
+///
+/// sRGB formats already hold encoded values, and with UNORM formats the
+/// presentation engine shows the stored bytes as sRGB anyway, so in both
+/// cases the bytes are kept as they are and only the channel order changes.
+pub(crate) fn to_rgba(bytes: &[u8], dimensions: [u32; 2], format: Format) -> Result<RgbaImage, MagmaError> {
+    bytes_per_pixel(format)?;
+    let mut pixels = bytes.to_vec();
+    if let Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb = format {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    RgbaImage::from_raw(dimensions[0], dimensions[1], pixels)
+        .ok_or_else(|| MagmaError::BufferAllocation("captured buffer is smaller than the image".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x1 image: an orange pixel and a half transparent blue one, stored
+    /// in BGRA order.
+    const BGRA: [u8; 8] = [0x10, 0x80, 0xff, 0xff, 0xc0, 0x20, 0x00, 0x80];
+    const RGBA: [u8; 8] = [0xff, 0x80, 0x10, 0xff, 0x00, 0x20, 0xc0, 0x80];
+
+    #[test]
+    fn bgra_is_swizzled_to_rgba() {
+        for &format in [Format::B8G8R8A8Unorm, Format::B8G8R8A8Srgb].iter() {
+            let image = to_rgba(&BGRA, [2, 1], format).unwrap();
+            assert_eq!(image.dimensions(), (2, 1));
+            assert_eq!(image.into_raw(), RGBA.to_vec(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn rgba_is_kept_as_is() {
+        for &format in [Format::R8G8B8A8Unorm, Format::R8G8B8A8Srgb].iter() {
+            let image = to_rgba(&RGBA, [2, 1], format).unwrap();
+            assert_eq!(image.into_raw(), RGBA.to_vec(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn other_formats_are_rejected() {
+        assert!(matches!(to_rgba(&RGBA, [2, 1], Format::R16G16B16A16Sfloat), Err(MagmaError::Swapchain(_))));
+    }
+
+    #[test]
+    fn short_buffers_are_rejected() {
+        assert!(matches!(to_rgba(&RGBA[.. 4], [2, 1], Format::R8G8B8A8Unorm), Err(MagmaError::BufferAllocation(_))));
+    }
+}
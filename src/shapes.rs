@@ -1,10 +1,13 @@
 
 use crate::core::{Renderer, Color, Vertex2DColor};
-use crate::camera::get_projection_matrix;
+use crate::camera::get_projection_matrix_z;
+use crate::error::MagmaError;
+use crate::debug::set_debug_name;
+use crate::cache::build_pipeline;
+use std::mem::size_of;
 use std::sync::Arc;
 use vulkano::pipeline::GraphicsPipeline;
 use vulkano::framebuffer::Subpass;
-use vulkano::buffer::{ CpuAccessibleBuffer, BufferUsage };
 use vulkano::descriptor::descriptor_set::{PersistentDescriptorSetImg, PersistentDescriptorSet, PersistentDescriptorSetSampler, FixedSizeDescriptorSetsPool};
 use glam::{mat4, vec3, vec4, Mat4, Quat, Vec2, Vec3, Vec4};
 
@@ -16,15 +19,21 @@ pub struct Rectangle {
 
 static mut ran: bool = false;
 impl Renderer {
-    pub fn rectangle(&mut self, rectangle: &Rectangle, color: Color) {
+    /// Draws `rectangle` at depth `z` in `-1.0..=1.0`, where higher values are
+    /// drawn in front when the depth buffer is enabled. Translucent colors
+    /// don't write depth, so draw them after what they should cover.
+    pub fn rectangle(&mut self, rectangle: &Rectangle, color: Color, z: f32) -> Result<(), MagmaError> {
+        self.rectangle_rgba(rectangle, self.output_rgba(color.normalise()), z)
+    }
+
+    /// `rectangle` with a color already converted by `output_rgba`.
+    pub(crate) fn rectangle_rgba(&mut self, rectangle: &Rectangle, format_color: [f32; 4], z: f32) -> Result<(), MagmaError> {
         if !self.pipelines.contains_key("rect") {
-            init_rect(self);
+            init_rect(self)?;
         }
-        let format_color = color.normalise();
-        let vertex_buffer = CpuAccessibleBuffer::<[Vertex2DColor]>::from_iter(
-            self.device.clone(),
-            BufferUsage::all(),
-            false,
+        let pipeline_name = if format_color[3] < 1.0 { "rect_blend" } else { "rect" };
+        let frame = &mut self.frames[self.current_frame];
+        let vertex_buffer = frame.rect_vertices.chunk(
             [
                 Vertex2DColor {
                     position: [-0.5, -0.5],
@@ -45,36 +54,36 @@ impl Renderer {
             ]
             .iter()
             .cloned(),
-        ).unwrap();
+        )?;
 
 
 
-        let ubuf = CpuAccessibleBuffer::from_data(self.device.clone(), BufferUsage::uniform_buffer(), true, {
+        let ubuf = frame.rect_uniforms.next({
             // note: this teapot was meant for OpenGL where the origin is at the lower left
             //       instead the origin is at the upper left in Vulkan, so we reverse the Y axis
-            let dimensions: [f32; 2] = self.dynamic_state.viewports.as_ref().unwrap().get(0).unwrap().dimensions;
+            let dimensions: [f32; 2] = self.projection_dimensions();
             //  let aspect_ratio = dimensions[0] / dimensions[1];
             
-            let mvp = get_projection_matrix(Vec2::new(rectangle.width as f32, rectangle.height as f32), rectangle.position, dimensions);
+            let mvp = get_projection_matrix_z(Vec2::new(rectangle.width as f32, rectangle.height as f32), rectangle.position, z, dimensions);
             rec_vs::ty::Data {
                mvp: [mvp.x_axis().into(), mvp.y_axis().into(), mvp.z_axis().into(), mvp.w_axis().into()],
             }
-        }).unwrap();
-        let pipeline = self.pipelines.get("rect").unwrap();
-        let p = pipeline.clone();
-        let layout = p.descriptor_set_layout(0).unwrap();
-        let set = Arc::new(PersistentDescriptorSet::start(layout.clone())
-            .add_buffer(ubuf).unwrap()
-            .build().unwrap()
-        );
+        })?;
+        let pipeline = self.pipelines.get(pipeline_name).unwrap().clone();
+        let set = frame.descriptor_pool(pipeline_name, &pipeline).next()
+            .add_buffer(ubuf)?
+            .build()?;
         
-        let cmb = self.command_buffer_builder.take().unwrap();
-        let res = cmb.draw(pipeline.clone(), &self.dynamic_state, vec!(vertex_buffer), set, ());
-        self.command_buffer_builder = Some(res.unwrap());
+        self.count_draw(&pipeline, 4, 1, 4 * size_of::<Vertex2DColor>() + size_of::<rec_vs::ty::Data>(), 0);
+        let mut cmb = self.take_command_buffer_builder()?;
+        let res = cmb.draw(pipeline, &self.dynamic_state, vertex_buffer, set, ()).map(|_| ());
+        self.command_buffer_builder = Some(cmb);
+        res?;
+        Ok(())
     }
 }
 
-mod rec_vs {
+pub(crate) mod rec_vs {
     vulkano_shaders::shader! {
         ty: "vertex",
         src: "
@@ -122,29 +131,32 @@ mod rec_fs {
 }
 
 
-pub fn init_rect(draw: &mut Renderer) {
-    let vs = rec_vs::Shader::load(draw.device.clone()).unwrap();
-    let fs = rec_fs::Shader::load(draw.device.clone()).unwrap();
-    println!("fires square");
-    let pipeline = GraphicsPipeline::start()
-    // Defines what kind of vertex input is expected.
-    .vertex_input_single_buffer::<crate::core::Vertex2DColor>()
-    // The vertex shader.
-    .vertex_shader(vs.main_entry_point(), ())
-    .triangle_strip()
-    // Defines the viewport (explanations below).
-    .viewports_dynamic_scissors_irrelevant(1)
-    // The fragment shader.
-    .fragment_shader(fs.main_entry_point(), ())
-    .blend_alpha_blending()
-    // This graphics pipeline object concerns the first pass of the render pass.
-    .render_pass(Subpass::from(draw.render_pass.clone(), 0).unwrap())
-    // Now that everything is specified, we call `build`.
-    .build(draw.device.clone())
-    .unwrap();
-
-    println!("fires square");
-    
-    draw.pipelines.insert("rect".to_string(), Arc::new(pipeline));
+pub fn init_rect(draw: &mut Renderer) -> Result<(), MagmaError> {
+    let vs = rec_vs::Shader::load(draw.device.clone())?;
+    let fs = rec_fs::Shader::load(draw.device.clone())?;
+    // Opaque rectangles write depth, translucent ones only test against it.
+    for &(name, depth_write) in [("rect", true), ("rect_blend", false)].iter() {
+        let pipeline = GraphicsPipeline::start()
+        // Defines what kind of vertex input is expected.
+        .vertex_input_single_buffer::<crate::core::Vertex2DColor>()
+        // The vertex shader.
+        .vertex_shader(vs.main_entry_point(), ())
+        .triangle_strip()
+        // Defines the viewport (explanations below).
+        .viewports_scissors_dynamic(1)
+        // The fragment shader.
+        .fragment_shader(fs.main_entry_point(), ())
+        .blend_alpha_blending()
+        .depth_stencil(draw.depth_stencil(depth_write))
+        // This graphics pipeline object concerns the first pass of the render pass.
+        .render_pass(Subpass::from(draw.render_pass.clone(), 0).unwrap());
+        // Now that everything is specified, we call `build`.
+        let pipeline = build_pipeline!(pipeline, draw.device, draw.pipeline_cache)?;
+        set_debug_name(&pipeline, name);
+
+        draw.insert_pipeline(name.to_string(), Arc::new(pipeline));
+    }
+
+    Ok(())
 }
 
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use vulkano::pipeline::GraphicsPipelineAbstract;
+
+use crate::core::Renderer;
+
+/// Frames the averages of `FrameStats` cover.
+const HISTORY: usize = 60;
+
+/// What one frame asked of the GPU, counted as draws are recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderCounters {
+    pub draw_calls: u32,
+    pub vertices: u64,
+    /// Draws that used a different pipeline from the draw before them.
+    pub pipeline_binds: u32,
+    pub descriptor_sets: u32,
+    /// Vertex and uniform data written for the GPU to read.
+    pub bytes_uploaded: u64,
+    pub textures_bound: u32,
+}
+
+/// `RenderCounters` averaged over recent frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AverageCounters {
+    pub draw_calls: f64,
+    pub vertices: f64,
+    pub pipeline_binds: f64,
+    pub descriptor_sets: f64,
+    pub bytes_uploaded: f64,
+    pub textures_bound: f64,
+}
+
+/// How long the GPU spent on one frame, measured with timestamp queries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GpuTimes {
+    /// The render target passes, see `Renderer::push_target`.
+    pub targets: Duration,
+    /// The frame's main render pass, post-processing included.
+    pub main: Duration,
+}
+
+/// Counters and timings of recent frames, from `Renderer::stats`.
+///
+/// GPU times come from timestamps written before and after the frame's
+/// passes, and stay `None` on queues that can't write timestamps. Frame
+/// latency instead runs from submitting a frame to seeing its fence
+/// signalled, so it also includes queueing behind earlier frames and waiting
+/// to present. Counting is a handful of integer adds per draw, so it's fine
+/// to leave on in release builds.
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    /// Counts for the last frame that finished recording.
+    pub last: RenderCounters,
+    /// GPU time of the last frame seen to complete.
+    pub gpu_time: Option<GpuTimes>,
+    /// Frame latency of the last frame seen to complete.
+    pub frame_latency: Option<Duration>,
+    history: VecDeque<RenderCounters>,
+    gpu_history: VecDeque<GpuTimes>,
+    latency_history: VecDeque<Duration>,
+}
+
+impl FrameStats {
+    /// Averages over up to the last 60 frames.
+    pub fn average(&self) -> AverageCounters {
+        let frames = self.history.len().max(1) as f64;
+        let mut average = AverageCounters::default();
+        for counters in self.history.iter() {
+            average.draw_calls += counters.draw_calls as f64;
+            average.vertices += counters.vertices as f64;
+            average.pipeline_binds += counters.pipeline_binds as f64;
+            average.descriptor_sets += counters.descriptor_sets as f64;
+            average.bytes_uploaded += counters.bytes_uploaded as f64;
+            average.textures_bound += counters.textures_bound as f64;
+        }
+        AverageCounters {
+            draw_calls: average.draw_calls / frames,
+            vertices: average.vertices / frames,
+            pipeline_binds: average.pipeline_binds / frames,
+            descriptor_sets: average.descriptor_sets / frames,
+            bytes_uploaded: average.bytes_uploaded / frames,
+            textures_bound: average.textures_bound / frames,
+        }
+    }
+
+    /// Average GPU time over up to the last 60 timed frames.
+    pub fn average_gpu_time(&self) -> Option<GpuTimes> {
+        if self.gpu_history.is_empty() {
+            return None;
+        }
+        let frames = self.gpu_history.len() as u32;
+        Some(GpuTimes {
+            targets: self.gpu_history.iter().map(|times| times.targets).sum::<Duration>() / frames,
+            main: self.gpu_history.iter().map(|times| times.main).sum::<Duration>() / frames,
+        })
+    }
+
+    /// Average frame latency over up to the last 60 completed frames.
+    pub fn average_frame_latency(&self) -> Option<Duration> {
+        if self.latency_history.is_empty() {
+            return None;
+        }
+        Some(self.latency_history.iter().sum::<Duration>() / self.latency_history.len() as u32)
+    }
+
+    pub(crate) fn end_frame(&mut self, counters: RenderCounters) {
+        self.last = counters;
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(counters);
+    }
+
+    pub(crate) fn frame_finished(&mut self, time: Duration) {
+        self.frame_latency = Some(time);
+        if self.latency_history.len() == HISTORY {
+            self.latency_history.pop_front();
+        }
+        self.latency_history.push_back(time);
+    }
+
+    pub(crate) fn gpu_finished(&mut self, times: GpuTimes) {
+        self.gpu_time = Some(times);
+        if self.gpu_history.len() == HISTORY {
+            self.gpu_history.pop_front();
+        }
+        self.gpu_history.push_back(times);
+    }
+}
+
+/// Counts for the frame being recorded.
+#[derive(Debug, Default)]
+pub(crate) struct FrameCounter {
+    counters: RenderCounters,
+    /// Address of the pipeline the last draw in the current command buffer
+    /// used, to tell when the next one needs a bind.
+    pub(crate) last_pipeline: Option<usize>,
+}
+
+impl Renderer {
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// Counts one draw call made with `pipeline`.
+    pub(crate) fn count_draw(
+        &mut self,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        vertices: usize,
+        descriptor_sets: usize,
+        bytes_uploaded: usize,
+        textures_bound: usize,
+    ) {
+        let address = &**pipeline as *const _ as *const () as usize;
+        let counter = &mut self.frame_counter;
+        if counter.last_pipeline != Some(address) {
+            counter.counters.pipeline_binds += 1;
+            counter.last_pipeline = Some(address);
+        }
+        counter.counters.draw_calls += 1;
+        counter.counters.vertices += vertices as u64;
+        counter.counters.descriptor_sets += descriptor_sets as u32;
+        counter.counters.bytes_uploaded += bytes_uploaded as u64;
+        counter.counters.textures_bound += textures_bound as u32;
+    }
+
+    /// Moves the recorded counts into `stats`, once the frame is submitted.
+    pub(crate) fn end_frame_stats(&mut self) {
+        let counters = mem::take(&mut self.frame_counter.counters);
+        self.frame_counter.last_pipeline = None;
+        self.stats.end_frame(counters);
+    }
+
+    /// Frees what finished frames held on to and picks up their latencies
+    /// and GPU times, without blocking.
+    pub(crate) fn poll_frames(&mut self) {
+        for frame in self.frames.iter_mut() {
+            frame.poll();
+        }
+        self.collect_frame_times();
+    }
+
+    pub(crate) fn collect_frame_times(&mut self) {
+        for frame in self.frames.iter_mut() {
+            if let Some(time) = frame.frame_latency.take() {
+                self.stats.frame_finished(time);
+            }
+            if let Some(times) = frame.gpu_times.take() {
+                self.stats.gpu_finished(times);
+            }
+        }
+    }
+
+    /// Marks the current frame as submitted now, for its latency.
+    pub(crate) fn frame_submitted(&mut self) {
+        self.frames[self.current_frame].submitted_at = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn empty_history_has_no_averages() {
+        let stats = FrameStats::default();
+        assert_eq!(stats.average_frame_latency(), None);
+        assert_eq!(stats.average_gpu_time(), None);
+        assert_eq!(stats.average(), AverageCounters::default());
+    }
+
+    #[test]
+    fn latency_average_covers_the_last_60_frames() {
+        let mut stats = FrameStats::default();
+        stats.frame_finished(ms(2));
+        stats.frame_finished(ms(4));
+        assert_eq!(stats.average_frame_latency(), Some(ms(3)));
+
+        // 60 more frames push the first two out of the window.
+        for _ in 0 .. HISTORY {
+            stats.frame_finished(ms(10));
+        }
+        assert_eq!(stats.average_frame_latency(), Some(ms(10)));
+        assert_eq!(stats.frame_latency, Some(ms(10)));
+    }
+
+    #[test]
+    fn gpu_average_covers_the_last_60_frames() {
+        let mut stats = FrameStats::default();
+        for i in 0 .. HISTORY as u64 + 10 {
+            stats.gpu_finished(GpuTimes { targets: ms(i), main: ms(2 * i) });
+        }
+        // Frames 10 to 69 remain, averaging 39.5.
+        assert_eq!(
+            stats.average_gpu_time(),
+            Some(GpuTimes { targets: Duration::from_micros(39_500), main: ms(79) }),
+        );
+    }
+
+    #[test]
+    fn counter_average_covers_the_last_60_frames() {
+        let mut stats = FrameStats::default();
+        let counters = |draw_calls| RenderCounters { draw_calls, vertices: 4 * draw_calls as u64, ..RenderCounters::default() };
+        for _ in 0 .. 30 {
+            stats.end_frame(counters(100));
+        }
+        for _ in 0 .. HISTORY {
+            stats.end_frame(counters(2));
+        }
+        stats.end_frame(counters(8));
+        assert_eq!(stats.last.draw_calls, 8);
+
+        // 59 frames of 2 and one of 8.
+        let average = stats.average();
+        assert!((average.draw_calls - 2.1).abs() < 1e-9);
+        assert!((average.vertices - 8.4).abs() < 1e-9);
+        assert_eq!(average.textures_bound, 0.0);
+    }
+}
@@ -0,0 +1,269 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract};
+use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage};
+use vulkano::pipeline::viewport::{Scissor, Viewport};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::sync::GpuFuture;
+
+use crate::core::{is_srgb, linear_rgba, Color, Renderer};
+use crate::create_render_pass;
+use crate::debug::set_debug_name;
+use crate::error::MagmaError;
+use crate::resolution::VirtualResolution;
+use crate::viewport::ViewportRegion;
+use crate::textures::Texture2D;
+use crate::timestamps::TIMESTAMP_START;
+
+/// Format `RenderTarget::new` uses. sRGB, so it blends and samples in
+/// linear space like an sRGB swapchain does.
+const TARGET_FORMAT: Format = Format::R8G8B8A8Srgb;
+
+/// An offscreen image that `Renderer::push_target` redirects drawing into,
+/// and that can then be drawn like any texture through `texture`.
+///
+/// A target can be pushed at most once per frame: every target pass runs
+/// before the frame's main pass, so a second push would replace what the
+/// first one drew before anything sampled it.
+pub struct RenderTarget {
+    pub width: u32,
+    pub height: u32,
+    pub clear_color: [f32; 4],
+    format: Format,
+    /// Drawn into in turn, one push each. There is one more than the frames
+    /// in flight, so the image a push overwrites was last sampled by a frame
+    /// the GPU has finished, and pushing never has to wait.
+    images: Vec<TargetImage>,
+    /// Index into `images` of the one pushed last.
+    current: Cell<usize>,
+    /// `Renderer::frame_index` of the last push.
+    pushed_in: Cell<Option<u64>>,
+    sampler: Arc<Sampler>,
+}
+
+struct TargetImage {
+    image: Arc<AttachmentImage>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+impl RenderTarget {
+    pub fn new(renderer: &mut Renderer, width: u32, height: u32) -> Result<RenderTarget, MagmaError> {
+        RenderTarget::with_format(renderer, width, height, TARGET_FORMAT)
+    }
+
+    /// Creates a target with a color `format` of its own. Pipelines are built
+    /// once per format the first time something is drawn into it.
+    pub fn with_format(
+        renderer: &mut Renderer,
+        width: u32,
+        height: u32,
+        format: Format,
+    ) -> Result<RenderTarget, MagmaError> {
+        let render_pass = renderer.target_pass(format)?.render_pass.clone();
+        let images = (0 .. renderer.frames_in_flight() + 1)
+            .map(|_| {
+                let image = AttachmentImage::with_usage(
+                    renderer.device.clone(),
+                    [width, height],
+                    format,
+                    ImageUsage {
+                        color_attachment: true,
+                        sampled: true,
+                        transfer_source: true,
+                        ..ImageUsage::none()
+                    },
+                )?;
+                set_debug_name(image.inner().image, "magma render target");
+
+                let framebuffer = Arc::new(
+                    Framebuffer::start(render_pass.clone())
+                        .add(image.clone())?
+                        .build()?,
+                ) as Arc<dyn FramebufferAbstract + Send + Sync>;
+                Ok(TargetImage { image, framebuffer })
+            })
+            .collect::<Result<Vec<_>, MagmaError>>()?;
+
+        let sampler = Sampler::new(renderer.device.clone(), Filter::Nearest, Filter::Nearest,
+            MipmapMode::Nearest, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge, 0.0, 1.0, 0.0, 0.0)?;
+
+        Ok(RenderTarget {
+            width,
+            height,
+            clear_color: [0.0, 0.0, 0.0, 0.0],
+            format,
+            images,
+            current: Cell::new(0),
+            pushed_in: Cell::new(None),
+            sampler,
+        })
+    }
+
+    /// Color the target is cleared to each time it is pushed.
+    pub fn clear(&mut self, color: Color) {
+        self.clear_color = color.normalise();
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The target's contents as a texture, for `Renderer::texture_pro` and
+    /// anything else that draws a `Texture2D`.
+    ///
+    /// Each push draws into a different image, so get the texture again after
+    /// pushing rather than keeping it across frames.
+    pub fn texture(&self) -> Texture2D {
+        Texture2D::from_image(
+            self.images[self.current.get()].image.clone(),
+            self.sampler.clone(),
+            self.width as i32,
+            self.height as i32,
+            false,
+            !is_srgb(self.format),
+        )
+    }
+}
+
+/// Render pass and pipelines shared by every target of one format.
+pub(crate) struct TargetPass {
+    pub(crate) render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pipelines: HashMap<String, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+}
+
+/// What `push_target` swapped out of the renderer, restored by `pop_target`.
+pub(crate) struct SavedTarget {
+    format: Format,
+    builder: Option<AutoCommandBufferBuilder>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pipelines: HashMap<String, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    dynamic_state: DynamicState,
+    scale_factor: f64,
+    framebuffer_size: [u32; 2],
+    virtual_resolution: Option<VirtualResolution>,
+    clip_stack: Vec<Scissor>,
+    viewport_region: Option<ViewportRegion>,
+    last_pipeline: Option<usize>,
+    output_srgb: bool,
+}
+
+impl Renderer {
+    pub(crate) fn target_pass(&mut self, format: Format) -> Result<&mut TargetPass, MagmaError> {
+        if !self.target_passes.contains_key(&format) {
+            let render_pass = create_render_pass(self.device.clone(), format, false, 1)?;
+            self.target_passes.insert(format, TargetPass {
+                render_pass,
+                pipelines: HashMap::new(),
+            });
+        }
+        Ok(self.target_passes.get_mut(&format).unwrap())
+    }
+
+    /// Sends every draw call until the matching `pop_target` into `target`,
+    /// which is cleared to its `clear_color` first. Fails if `target` was
+    /// already pushed this frame.
+    pub fn push_target(&mut self, target: &RenderTarget) -> Result<(), MagmaError> {
+        if target.pushed_in.get() == Some(self.frame_index) {
+            return Err(MagmaError::Pipeline("a render target can only be pushed once per frame".to_string()));
+        }
+        let index = (target.current.get() + 1) % target.images.len();
+
+        let srgb = is_srgb(target.format);
+        let clear_color = if srgb { linear_rgba(target.clear_color) } else { target.clear_color };
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())?;
+        builder.begin_render_pass(target.images[index].framebuffer.clone(), false, vec![clear_color.into()])?;
+        let pass = self.target_pass(target.format)?;
+        let render_pass = pass.render_pass.clone();
+        let pipelines = mem::replace(&mut pass.pipelines, HashMap::new());
+        // Only count the push once nothing above can fail, so a failed push
+        // can be retried.
+        target.pushed_in.set(Some(self.frame_index));
+        target.current.set(index);
+        let dynamic_state = DynamicState {
+            viewports: Some(vec![Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [target.width as f32, target.height as f32],
+                depth_range: 0.0..1.0,
+            }]),
+            scissors: Some(vec![Scissor::irrelevant()]),
+            ..DynamicState::none()
+        };
+
+        let saved = SavedTarget {
+            format: target.format,
+            builder: mem::replace(&mut self.command_buffer_builder, Some(builder)),
+            render_pass: mem::replace(&mut self.render_pass, render_pass),
+            pipelines: mem::replace(&mut self.pipelines, pipelines),
+            dynamic_state: mem::replace(&mut self.dynamic_state, dynamic_state),
+            scale_factor: mem::replace(&mut self.scale_factor, 1.0),
+            framebuffer_size: mem::replace(&mut self.framebuffer_size, [target.width, target.height]),
+            virtual_resolution: self.virtual_resolution.take(),
+            clip_stack: mem::replace(&mut self.clip_stack, Vec::new()),
+            viewport_region: self.viewport_region.take(),
+            last_pipeline: self.frame_counter.last_pipeline.take(),
+            output_srgb: mem::replace(&mut self.output_srgb, srgb),
+        };
+        self.target_stack.push(saved);
+        Ok(())
+    }
+
+    /// Finishes drawing into the target pushed last and goes back to the
+    /// previous one. The target's contents are ready to sample from the next
+    /// draw call on.
+    pub fn pop_target(&mut self) -> Result<(), MagmaError> {
+        let saved = self.target_stack.pop().ok_or_else(|| {
+            MagmaError::Pipeline("pop_target called without a pushed target".to_string())
+        })?;
+        let command_buffer = self.take_command_buffer_builder();
+
+        self.command_buffer_builder = saved.builder;
+        self.render_pass = saved.render_pass;
+        self.dynamic_state = saved.dynamic_state;
+        self.scale_factor = saved.scale_factor;
+        self.framebuffer_size = saved.framebuffer_size;
+        self.virtual_resolution = saved.virtual_resolution;
+        self.clip_stack = saved.clip_stack;
+        self.viewport_region = saved.viewport_region;
+        self.frame_counter.last_pipeline = saved.last_pipeline;
+        self.output_srgb = saved.output_srgb;
+        let pipelines = mem::replace(&mut self.pipelines, saved.pipelines);
+        self.target_pass(saved.format)?.pipelines = pipelines;
+
+        let mut command_buffer = command_buffer?;
+        command_buffer.end_render_pass()?;
+        self.target_commands.push(command_buffer.build()?);
+        Ok(())
+    }
+
+    /// Pops any target left pushed at the end of a frame, so the frame's own
+    /// command buffer is back in place, and reports the mistake.
+    pub(crate) fn end_targets(&mut self) -> Result<(), MagmaError> {
+        if self.target_stack.is_empty() {
+            return Ok(());
+        }
+        while !self.target_stack.is_empty() {
+            self.pop_target()?;
+        }
+        Err(MagmaError::Pipeline("a render target was pushed but never popped".to_string()))
+    }
+
+    /// Chains the passes recorded into render targets this frame onto
+    /// `future`, so they run before the command buffer that samples them.
+    pub(crate) fn execute_target_passes(
+        &mut self,
+        future: Box<dyn GpuFuture>,
+    ) -> Result<Box<dyn GpuFuture>, MagmaError> {
+        let mut future = self.mark_timestamp(future, TIMESTAMP_START)?;
+        for command_buffer in self.target_commands.drain(..) {
+            future = Box::new(future.then_execute(self.queue.clone(), command_buffer)?);
+        }
+        Ok(future)
+    }
+}
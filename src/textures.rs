@@ -1,5 +1,9 @@
 use crate::core::{Renderer, MamgaGfx, Color, Vertex2D};
-use crate::camera::get_projection_matrix;
+use crate::camera::get_projection_matrix_z;
+use crate::error::MagmaError;
+use crate::debug::set_debug_name;
+use crate::cache::build_pipeline;
+use std::mem::size_of;
 use std::sync::Arc;
 use vulkano::pipeline::{
     GraphicsPipeline,
@@ -8,83 +12,122 @@ use vulkano::pipeline::{
 
 
 use vulkano::framebuffer::Subpass;
-use vulkano::buffer::{ CpuAccessibleBuffer, BufferUsage };
-use vulkano::image::ImmutableImage;
+use vulkano::image::{ImageAccess, ImageViewAccess, ImmutableImage};
 use vulkano::image::Dimensions;
-use vulkano::format::R8G8B8A8Unorm;
+use vulkano::format::{R8G8B8A8Srgb, R8G8B8A8Unorm};
 use image::GenericImageView;
 use vulkano::descriptor::descriptor_set::{PersistentDescriptorSetImg, PersistentDescriptorSet, PersistentDescriptorSetSampler, FixedSizeDescriptorSetsPool};
 use vulkano::sampler::{Sampler, SamplerAddressMode, Filter, MipmapMode};
-use vulkano::buffer::cpu_pool::CpuBufferPool;
 
 use glam::{mat4, vec3, vec4, Mat4, Quat, Vec2, Vec3, Vec4};
 
 pub struct Texture2D {
     pub width: i32,
     pub height: i32,
-    ubuf: CpuBufferPool<texture_vs::ty::Data>,
-    pool: FixedSizeDescriptorSetsPool,
-    sampler: Arc<Sampler>,
-    image: Arc<ImmutableImage<R8G8B8A8Unorm>>
+    pub(crate) sampler: Arc<Sampler>,
+    /// Every texel is either fully opaque or fully transparent, so the
+    /// texture can write depth with the transparent texels discarded.
+    opaque: bool,
+    /// Texels are sampled as stored instead of decoded from sRGB.
+    linear: bool,
+    pub(crate) image: Arc<dyn ImageViewAccess + Send + Sync>
 }
 
 
 impl Texture2D {
-    pub fn load(gfx: &mut MamgaGfx, path: String) -> Texture2D {
-        
-        let vs = texture_vs::Shader::load(gfx.device.clone()).unwrap();
-        let fs = texture_fs::Shader::load(gfx.device.clone()).unwrap();
-        
-        let pipeline = GraphicsPipeline::start()
-        // Defines what kind of vertex input is expected.
-        .vertex_input_single_buffer::<crate::core::Vertex2D>()
-        // The vertex shader.
-        .vertex_shader(vs.main_entry_point(), ())
-        .triangle_strip()
-        // Defines the viewport (explanations below).
-        .viewports_dynamic_scissors_irrelevant(1)
-        // The fragment shader.
-        .fragment_shader(fs.main_entry_point(), ())
-        .blend_alpha_blending()
-        // This graphics pipeline object concerns the first pass of the render pass.
-        .render_pass(Subpass::from(gfx.renderer.render_pass.clone(), 0).unwrap())
-        // Now that everything is specified, we call `build`.
-        .build(gfx.renderer.device.clone())
-        .unwrap();
-        let ubuf = CpuBufferPool::new(gfx.device.clone(), BufferUsage::uniform_buffer());
+    /// Wraps an image that is already on the GPU, such as a
+    /// `RenderTarget`'s. `opaque` textures write depth.
+    pub(crate) fn from_image(
+        image: Arc<dyn ImageViewAccess + Send + Sync>,
+        sampler: Arc<Sampler>,
+        width: i32,
+        height: i32,
+        opaque: bool,
+        linear: bool,
+    ) -> Texture2D {
+        Texture2D {
+            image,
+            sampler,
+            opaque,
+            linear,
+            width,
+            height,
+        }
+    }
+
+    /// Loads a color texture, uploaded as `R8G8B8A8Srgb` so it's decoded to
+    /// linear when sampled.
+    pub fn load(gfx: &mut MamgaGfx, path: String) -> Result<Texture2D, MagmaError> {
+        Texture2D::load_with(&mut gfx.renderer, path)
+    }
+
+    /// Loads a texture using only the renderer, which lets offscreen renderers
+    /// without a `MamgaGfx` (see `headless`) load textures too.
+    pub fn load_with(renderer: &mut Renderer, path: String) -> Result<Texture2D, MagmaError> {
+        Texture2D::load_image(renderer, path, false)
+    }
+
+    /// Loads a data texture, such as a normal or lookup map, uploaded as
+    /// `R8G8B8A8Unorm` so shaders see the stored values unchanged.
+    pub fn load_linear(gfx: &mut MamgaGfx, path: String) -> Result<Texture2D, MagmaError> {
+        Texture2D::load_linear_with(&mut gfx.renderer, path)
+    }
+
+    pub fn load_linear_with(renderer: &mut Renderer, path: String) -> Result<Texture2D, MagmaError> {
+        Texture2D::load_image(renderer, path, true)
+    }
+
+    /// Whether the texture is sampled as stored rather than decoded from sRGB.
+    pub fn is_linear(&self) -> bool {
+        self.linear
+    }
+
+    fn load_image(renderer: &mut Renderer, path: String, linear: bool) -> Result<Texture2D, MagmaError> {
+        if !renderer.pipelines.contains_key("texture") {
+            init_texture(renderer)?;
+        }
         
-        let loaded_image = image::open(path).unwrap();
+        let loaded_image = image::open(&path)?;
         let dimensions = loaded_image.dimensions();
         let image = loaded_image.to_rgba().to_vec();
+        let opaque = image.chunks(4).all(|texel| texel[3] == 0 || texel[3] == 255);
         
-        let (texture, _tex_future) = {
-            ImmutableImage::from_iter(
+        let image_dimensions = Dimensions::Dim2d { width: dimensions.0, height: dimensions.1 };
+        // The formats are distinct types, so each upload gets its own branch.
+        let texture: Arc<dyn ImageViewAccess + Send + Sync> = if linear {
+            let (texture, _tex_future) = ImmutableImage::from_iter(
                 image.iter().cloned(),
-                Dimensions::Dim2d { width: dimensions.0, height: dimensions.1 },
+                image_dimensions,
                 R8G8B8A8Unorm,
-                gfx.queue.clone()
-            ).unwrap()
+                renderer.queue.clone()
+            )?;
+            set_debug_name(texture.inner().image, &path);
+            texture
+        } else {
+            let (texture, _tex_future) = ImmutableImage::from_iter(
+                image.iter().cloned(),
+                image_dimensions,
+                R8G8B8A8Srgb,
+                renderer.queue.clone()
+            )?;
+            set_debug_name(texture.inner().image, &path);
+            texture
         };
 
         //let uniform_buffer = CpuBufferPool::<texture_vs::ty::Data>::new(gfx.device.clone(), BufferUsage::all());
 
-        let sampler = Sampler::new(gfx.device.clone(), Filter::Nearest, Filter::Nearest,
+        let sampler = Sampler::new(renderer.device.clone(), Filter::Nearest, Filter::Nearest,
         MipmapMode::Nearest, SamplerAddressMode::Repeat, SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat, 0.0, 1.0, 0.0, 0.0).unwrap();
-        
-        let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
-        let pool = FixedSizeDescriptorSetsPool::new(layout.clone());
-
-        gfx.renderer.pipelines.insert("texture".to_string(), Arc::new(pipeline));
+        SamplerAddressMode::Repeat, 0.0, 1.0, 0.0, 0.0)?;
        
-        Texture2D {
+        Ok(Texture2D {
             image: texture,
-            ubuf,
             sampler,
-            pool,
+            opaque,
+            linear,
             width: dimensions.0 as i32,
             height: dimensions.1 as i32
-        }
+        })
     }
 }
 
@@ -93,14 +136,22 @@ static mut ran: bool = false;
 
 
 impl Renderer {
-    pub fn texture(&mut self, texture: &mut Texture2D, position: Vec2) {
-        self.texture_pro(texture, position, 1.0);
+    pub fn texture(&mut self, texture: &mut Texture2D, position: Vec2) -> Result<(), MagmaError> {
+        self.texture_pro(texture, position, 1.0, 0.0)
     }
-    pub fn texture_pro(&mut self, texture: &mut Texture2D, position: Vec2, scale: f32) {
-        let vertex_buffer = CpuAccessibleBuffer::<[Vertex2D]>::from_iter(
-            self.device.clone(),
-            BufferUsage::all(),
-            false,
+    /// Draws `texture` scaled by `scale` at depth `z` in `-1.0..=1.0`, where
+    /// higher values are drawn in front when the depth buffer is enabled.
+    /// Textures with partially transparent texels don't write depth, so draw
+    /// them after what they should cover.
+    pub fn texture_pro(&mut self, texture: &mut Texture2D, position: Vec2, scale: f32, z: f32) -> Result<(), MagmaError> {
+        // Pipelines are per render pass, and a pushed render target may not
+        // have drawn a texture yet.
+        if !self.pipelines.contains_key("texture") {
+            init_texture(self)?;
+        }
+        let pipeline_name = if texture.opaque { "texture" } else { "texture_blend" };
+        let frame = &mut self.frames[self.current_frame];
+        let vertex_buffer = frame.texture_vertices.chunk(
             [
                 Vertex2D {
                     position: [-0.5, -0.5],
@@ -117,31 +168,68 @@ impl Renderer {
             ]
             .iter()
             .cloned()
-    ).unwrap();
+    )?;
 
     let uniform_buffer_subbuffer = {
         // note: this teapot was meant for OpenGL where the origin is at the lower left
         //       instead the origin is at the upper left in Vulkan, so we reverse the Y axis
-        let dimensions: [f32; 2] = self.dynamic_state.viewports.as_ref().unwrap().get(0).unwrap().dimensions;
-        let projection = get_projection_matrix(Vec2::new(texture.width as f32 * scale, texture.height as f32 * scale), position, dimensions);
+        let dimensions: [f32; 2] = self.projection_dimensions();
+        let projection = get_projection_matrix_z(Vec2::new(texture.width as f32 * scale, texture.height as f32 * scale), position, z, dimensions);
         
         let uniform_data = texture_vs::ty::Data {
             projection: [projection.x_axis().into(), projection.y_axis().into(), projection.z_axis().into(), projection.w_axis().into()]
         };
         
-        texture.ubuf.next(uniform_data).unwrap()
+        frame.texture_uniforms.next(uniform_data)?
     };
 
-    let set = texture.pool.next()
-    .add_buffer(uniform_buffer_subbuffer).unwrap()
-    .add_sampled_image(texture.image.clone(), texture.sampler.clone()).unwrap()
+    let pipeline = self.pipelines.get(pipeline_name).unwrap().clone();
+    let set = frame.descriptor_pool(pipeline_name, &pipeline).next()
+    .add_buffer(uniform_buffer_subbuffer)?
+    .add_sampled_image(texture.image.clone(), texture.sampler.clone())?
     
-    .build().unwrap();
-    let cmb = self.command_buffer_builder.take().unwrap();
-    let res = cmb.draw(self.pipelines.get("texture").unwrap().clone(), &self.dynamic_state, vec!(vertex_buffer), set, ());
-    self.command_buffer_builder = Some(res.unwrap());
+    .build()?;
+    self.count_draw(&pipeline, 4, 1, 4 * size_of::<Vertex2D>() + size_of::<texture_vs::ty::Data>(), 1);
+    let mut cmb = self.take_command_buffer_builder()?;
+    let res = cmb.draw(pipeline, &self.dynamic_state, vertex_buffer, set, ()).map(|_| ());
+    self.command_buffer_builder = Some(cmb);
+    res?;
+    Ok(())
+}
+
 }
 
+
+pub fn init_texture(renderer: &mut Renderer) -> Result<(), MagmaError> {
+    let vs = texture_vs::Shader::load(renderer.device.clone())?;
+    let fs = texture_fs::Shader::load(renderer.device.clone())?;
+    // Sampling decodes sRGB textures to linear, which only an sRGB output
+    // encodes back on write; otherwise the shader has to.
+    let constants = texture_fs::SpecializationConstants { encode_srgb: !renderer.output_srgb as u32 };
+    
+    // Opaque textures write depth, translucent ones only test against it.
+    for &(name, depth_write) in [("texture", true), ("texture_blend", false)].iter() {
+        let pipeline = GraphicsPipeline::start()
+        // Defines what kind of vertex input is expected.
+        .vertex_input_single_buffer::<crate::core::Vertex2D>()
+        // The vertex shader.
+        .vertex_shader(vs.main_entry_point(), ())
+        .triangle_strip()
+        // Defines the viewport (explanations below).
+        .viewports_scissors_dynamic(1)
+        // The fragment shader.
+        .fragment_shader(fs.main_entry_point(), constants)
+        .blend_alpha_blending()
+        .depth_stencil(renderer.depth_stencil(depth_write))
+        // This graphics pipeline object concerns the first pass of the render pass.
+        .render_pass(Subpass::from(renderer.render_pass.clone(), 0).unwrap());
+        // Now that everything is specified, we call `build`.
+        let pipeline = build_pipeline!(pipeline, renderer.device, renderer.pipeline_cache)?;
+        set_debug_name(&pipeline, name);
+
+        renderer.insert_pipeline(name.to_string(), Arc::new(pipeline));
+    }
+    Ok(())
 }
 
 
@@ -175,9 +263,18 @@ mod texture_fs {
             layout(location = 0) in vec2 tex_coords;
             layout(location = 0) out vec4 f_color;
             layout(set = 0, binding = 1) uniform sampler2D tex;
+            layout(constant_id = 0) const bool encode_srgb = false;
 
             void main() {
                 f_color = texture(tex, tex_coords);
+                // Keeps fully transparent texels out of the depth buffer.
+                if (f_color.a == 0.0) {
+                    discard;
+                }
+                if (encode_srgb) {
+                    vec3 c = f_color.rgb;
+                    f_color.rgb = mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+                }
             }
         "
     }
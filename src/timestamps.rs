@@ -0,0 +1,222 @@
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::pool::{CommandPool, StandardCommandPoolAlloc};
+use vulkano::command_buffer::sys::{Flags, Kind, UnsafeCommandBuffer, UnsafeCommandBufferBuilder};
+use vulkano::command_buffer::{AutoCommandBuffer, CommandBuffer, CommandBufferExecError};
+use vulkano::device::{Device, DeviceOwned, Queue};
+use vulkano::image::{ImageAccess, ImageLayout};
+use vulkano::query::{QueryType, UnsafeQueryPool};
+use vulkano::sync::{AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages};
+use vulkano::VulkanObject;
+
+use crate::core::Renderer;
+use crate::error::MagmaError;
+use crate::stats::GpuTimes;
+
+/// Written before the frame's render target passes.
+pub(crate) const TIMESTAMP_START: u32 = 0;
+/// Written once the target passes are done and the swapchain image is ready.
+pub(crate) const TIMESTAMP_TARGETS: u32 = 1;
+/// Written once the main pass is done.
+pub(crate) const TIMESTAMP_MAIN: u32 = 2;
+const QUERIES: u32 = 3;
+
+/// `VK_QUERY_RESULT_64_BIT`.
+const QUERY_RESULT_64_BIT: u32 = 0x1;
+/// `VK_SUCCESS`.
+const SUCCESS: u32 = 0;
+
+/// Timestamp queries for one frame in flight.
+///
+/// vulkano's `AutoCommandBufferBuilder` can't write timestamps, so each one
+/// goes in a command buffer of its own submitted between the frame's passes.
+/// Timestamps are written at the bottom of the pipe, so each marks when
+/// everything submitted before it has finished.
+pub(crate) struct FrameTimer {
+    device: Arc<Device>,
+    pool: Arc<UnsafeQueryPool>,
+    valid_bits: u32,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    /// Whether every query was submitted since the results were last read.
+    written: bool,
+}
+
+impl FrameTimer {
+    /// `None` when `queue` can't write timestamps.
+    pub(crate) fn new(device: Arc<Device>, queue: &Queue) -> Option<FrameTimer> {
+        let valid_bits = queue.family().timestamp_valid_bits().filter(|&bits| bits > 0)?;
+        let period = device.physical_device().limits().timestamp_period();
+        let pool = UnsafeQueryPool::new(device.clone(), QueryType::Timestamp, QUERIES).ok()?;
+        Some(FrameTimer {
+            device,
+            pool: Arc::new(pool),
+            valid_bits,
+            period,
+            written: false,
+        })
+    }
+
+    /// Chains a command buffer writing timestamp `query` onto `future`.
+    /// `TIMESTAMP_START` also resets the queries from the frame before.
+    fn mark(
+        &mut self,
+        future: Box<dyn GpuFuture>,
+        queue: &Arc<Queue>,
+        query: u32,
+    ) -> Result<Box<dyn GpuFuture>, MagmaError> {
+        let alloc = Device::standard_command_pool(&self.device, queue.family())
+            .alloc(false, 1)?
+            .next()
+            .unwrap();
+        let inner = unsafe {
+            let mut builder = UnsafeCommandBufferBuilder::new(alloc, Kind::primary(), Flags::OneTimeSubmit)?;
+            if query == TIMESTAMP_START {
+                builder.reset_query_pool(self.pool.queries_range(0, QUERIES).unwrap());
+            }
+            builder.write_timestamp(
+                self.pool.query(query).unwrap(),
+                PipelineStages { bottom_of_pipe: true, ..PipelineStages::none() },
+            );
+            builder.build()?
+        };
+        let commands = TimestampCommands {
+            inner,
+            device: self.device.clone(),
+            _pool: self.pool.clone(),
+        };
+        self.written = query == TIMESTAMP_MAIN;
+        Ok(Box::new(future.then_execute(queue.clone(), commands)?))
+    }
+
+    /// GPU time of the last frame marked, once its fence has signalled.
+    pub(crate) fn read(&mut self) -> Option<GpuTimes> {
+        if !mem::replace(&mut self.written, false) {
+            return None;
+        }
+        let mut ticks = [0u64; QUERIES as usize];
+        let result = unsafe {
+            self.device.pointers().GetQueryPoolResults(
+                self.device.internal_object(),
+                self.pool.internal_object(),
+                0,
+                QUERIES,
+                mem::size_of_val(&ticks),
+                ticks.as_mut_ptr() as *mut _,
+                mem::size_of::<u64>() as u64,
+                QUERY_RESULT_64_BIT,
+            )
+        };
+        // Anything else, including VK_NOT_READY, leaves the frame untimed.
+        if result != SUCCESS {
+            return None;
+        }
+        Some(GpuTimes {
+            targets: ticks_to_duration(ticks[0], ticks[1], self.valid_bits, self.period),
+            main: ticks_to_duration(ticks[1], ticks[2], self.valid_bits, self.period),
+        })
+    }
+}
+
+/// Time between two timestamps, which only have `valid_bits` significant
+/// bits and so may have wrapped around in between.
+fn ticks_to_duration(start: u64, end: u64, valid_bits: u32, period: f32) -> Duration {
+    let mask = if valid_bits >= 64 { u64::MAX } else { (1 << valid_bits) - 1 };
+    let ticks = end.wrapping_sub(start) & mask;
+    Duration::from_nanos((ticks as f64 * period as f64) as u64)
+}
+
+/// A command buffer that only writes a timestamp, which uses no buffers or
+/// images for vulkano to track.
+struct TimestampCommands {
+    inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+    device: Arc<Device>,
+    /// Kept alive until the GPU has written the query.
+    _pool: Arc<UnsafeQueryPool>,
+}
+
+unsafe impl DeviceOwned for TimestampCommands {
+    fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+}
+
+unsafe impl CommandBuffer for TimestampCommands {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> {
+        &self.inner
+    }
+
+    fn lock_submit(&self, _future: &dyn GpuFuture, _queue: &Queue) -> Result<(), CommandBufferExecError> {
+        Ok(())
+    }
+
+    unsafe fn unlock(&self) {}
+
+    fn check_buffer_access(
+        &self,
+        _buffer: &dyn BufferAccess,
+        _exclusive: bool,
+        _queue: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+
+    fn check_image_access(
+        &self,
+        _image: &dyn ImageAccess,
+        _layout: ImageLayout,
+        _exclusive: bool,
+        _queue: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+}
+
+impl Renderer {
+    /// Runs the frame's main `command_buffer` after `future`, which should
+    /// already include the target passes, timing it when timestamps are
+    /// supported.
+    pub(crate) fn execute_frame(
+        &mut self,
+        future: Box<dyn GpuFuture>,
+        command_buffer: AutoCommandBuffer,
+    ) -> Result<Box<dyn GpuFuture>, MagmaError> {
+        let future = self.mark_timestamp(future, TIMESTAMP_TARGETS)?;
+        let future = Box::new(future.then_execute(self.queue.clone(), command_buffer)?) as Box<dyn GpuFuture>;
+        self.mark_timestamp(future, TIMESTAMP_MAIN)
+    }
+
+    pub(crate) fn mark_timestamp(
+        &mut self,
+        future: Box<dyn GpuFuture>,
+        query: u32,
+    ) -> Result<Box<dyn GpuFuture>, MagmaError> {
+        match self.frames[self.current_frame].timer.as_mut() {
+            Some(timer) => timer.mark(future, &self.queue, query),
+            None => Ok(future),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_are_scaled_by_the_period() {
+        assert_eq!(ticks_to_duration(1_000, 5_000, 64, 1.0), Duration::from_nanos(4_000));
+        assert_eq!(ticks_to_duration(1_000, 5_000, 64, 2.5), Duration::from_nanos(10_000));
+    }
+
+    #[test]
+    fn ticks_wrap_at_the_valid_bits() {
+        let max = (1u64 << 36) - 1;
+        assert_eq!(ticks_to_duration(max - 9, 10, 36, 1.0), Duration::from_nanos(20));
+        assert_eq!(ticks_to_duration(u64::MAX - 9, 10, 64, 1.0), Duration::from_nanos(20));
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+
+/// Longest frame the fixed timestep will try to catch up on, so a stall
+/// (dragging the window, a breakpoint) doesn't trigger hundreds of updates.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Timing information handed to the update callback every frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameContext {
+    /// Seconds since the previous frame.
+    pub delta_time: f32,
+    /// Seconds since the run loop started.
+    pub elapsed: f32,
+    /// Number of frames rendered before this one.
+    pub frame: u64,
+    /// Length of one fixed update in seconds, when a fixed timestep is set.
+    pub fixed_delta_time: Option<f32>,
+    /// How many fixed updates to run this frame. Always 0 without a fixed timestep.
+    pub fixed_steps: u32,
+    /// How far between the last fixed update and the next one this frame is
+    /// drawn, in `0.0..1.0`, for interpolating state. 1.0 without a fixed timestep.
+    pub alpha: f32,
+}
+
+/// Measures frame times for the run loop and optionally caps the frame rate.
+pub struct FrameClock {
+    start: Instant,
+    last: Instant,
+    frame: u64,
+    accumulator: Duration,
+    target_fps: Option<f64>,
+    fixed_timestep: Option<Duration>,
+}
+
+impl FrameClock {
+    /// A frame rate that isn't positive and finite, or a zero timestep,
+    /// counts as not set.
+    pub fn new(target_fps: Option<f64>, fixed_timestep: Option<Duration>) -> Self {
+        let now = Instant::now();
+        FrameClock {
+            start: now,
+            last: now,
+            frame: 0,
+            accumulator: Duration::from_secs(0),
+            target_fps: target_fps.filter(|&fps| valid_fps(fps)),
+            fixed_timestep: fixed_timestep.filter(|&step| valid_timestep(step)),
+        }
+    }
+
+    /// Starts a new frame and returns its timing.
+    pub fn tick(&mut self) -> FrameContext {
+        self.tick_at(Instant::now())
+    }
+
+    fn tick_at(&mut self, now: Instant) -> FrameContext {
+        let delta = now - self.last;
+        self.last = now;
+
+        let (fixed_steps, alpha) = match self.fixed_timestep {
+            Some(step) => {
+                self.accumulator += delta.min(MAX_FRAME_TIME);
+                let mut steps = 0;
+                while self.accumulator >= step {
+                    self.accumulator -= step;
+                    steps += 1;
+                }
+                (steps, self.accumulator.as_secs_f32() / step.as_secs_f32())
+            }
+            None => (0, 1.0),
+        };
+
+        let context = FrameContext {
+            delta_time: delta.as_secs_f32(),
+            elapsed: (now - self.start).as_secs_f32(),
+            frame: self.frame,
+            fixed_delta_time: self.fixed_timestep.map(|step| step.as_secs_f32()),
+            fixed_steps,
+            alpha,
+        };
+        self.frame += 1;
+        context
+    }
+
+    /// Sleeps out the rest of the frame when a target frame rate is set.
+    pub fn limit(&self) {
+        if let Some(fps) = self.target_fps {
+            let target = Duration::from_secs_f64(1.0 / fps);
+            let spent = self.last.elapsed();
+            if spent < target {
+                spin_sleep::sleep(target - spent);
+            }
+        }
+    }
+}
+
+pub(crate) fn valid_fps(fps: f64) -> bool {
+    fps.is_finite() && fps > 0.0
+}
+
+pub(crate) fn valid_timestep(step: Duration) -> bool {
+    step > Duration::from_secs(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn without_fixed_timestep_no_steps_run() {
+        let mut clock = FrameClock::new(None, None);
+        let start = clock.last;
+        let frame = clock.tick_at(start + ms(16));
+        assert_eq!(frame.fixed_steps, 0);
+        assert_eq!(frame.alpha, 1.0);
+        assert_eq!(frame.fixed_delta_time, None);
+    }
+
+    #[test]
+    fn steps_and_alpha_follow_elapsed_time() {
+        let mut clock = FrameClock::new(None, Some(ms(10)));
+        let start = clock.last;
+        let frame = clock.tick_at(start + ms(25));
+        assert_eq!(frame.fixed_steps, 2);
+        assert!((frame.alpha - 0.5).abs() < 1e-4);
+
+        // The 5ms left over carries into the next frame.
+        let frame = clock.tick_at(start + ms(33));
+        assert_eq!(frame.fixed_steps, 1);
+        assert!((frame.alpha - 0.3).abs() < 1e-4);
+
+        let frame = clock.tick_at(start + ms(35));
+        assert_eq!(frame.fixed_steps, 0);
+        assert!((frame.alpha - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_frames_are_capped() {
+        let mut clock = FrameClock::new(None, Some(ms(10)));
+        let start = clock.last;
+        let frame = clock.tick_at(start + Duration::from_secs(5));
+        assert_eq!(frame.fixed_steps, 25);
+        assert_eq!(frame.alpha, 0.0);
+    }
+
+    #[test]
+    fn invalid_settings_are_ignored() {
+        let mut clock = FrameClock::new(Some(0.0), Some(ms(0)));
+        let start = clock.last;
+        let frame = clock.tick_at(start + ms(16));
+        assert_eq!(frame.fixed_steps, 0);
+        assert_eq!(frame.fixed_delta_time, None);
+        assert_eq!(clock.target_fps, None);
+
+        assert_eq!(FrameClock::new(Some(-30.0), None).target_fps, None);
+        assert_eq!(FrameClock::new(Some(f64::NAN), None).target_fps, None);
+        assert_eq!(FrameClock::new(Some(60.0), None).target_fps, Some(60.0));
+    }
+}
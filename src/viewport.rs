@@ -0,0 +1,58 @@
+use vulkano::pipeline::viewport::Viewport;
+
+use crate::core::Renderer;
+use crate::error::MagmaError;
+use crate::shapes::Rectangle;
+
+/// A region of the screen set with `Renderer::set_viewport`.
+#[derive(Debug, Clone)]
+pub(crate) struct ViewportRegion {
+    /// The full-screen viewport to go back to.
+    screen: Viewport,
+    /// Size of the screen in draw coordinates.
+    screen_units: [f32; 2],
+    /// Size of the region in draw coordinates, which projection now maps
+    /// across the region.
+    pub(crate) size: [f32; 2],
+}
+
+impl Renderer {
+    /// Draws everything that follows into `region` of the screen, given in
+    /// the screen's draw coordinates, e.g. the left half for split-screen.
+    ///
+    /// Inside the region, draw coordinates start at its top left and cover
+    /// `region.width` by `region.height`, so the same scene code fills any
+    /// region. Anything outside it is cut off. Regions don't nest: each call
+    /// is relative to the whole screen.
+    pub fn set_viewport(&mut self, region: &Rectangle) -> Result<(), MagmaError> {
+        let (screen, screen_units) = match self.viewport_region.take() {
+            Some(current) => (current.screen, current.screen_units),
+            None => {
+                let screen = self.dynamic_state.viewports.as_ref().and_then(|viewports| viewports.get(0)).cloned()
+                    .ok_or_else(|| MagmaError::Pipeline("set_viewport needs a screen viewport to divide".to_string()))?;
+                (screen, self.projection_dimensions())
+            }
+        };
+        let scale = [screen.dimensions[0] / screen_units[0], screen.dimensions[1] / screen_units[1]];
+        let size = [region.width as f32, region.height as f32];
+        let top_left = [region.position.x() - size[0] / 2.0, region.position.y() - size[1] / 2.0];
+
+        self.dynamic_state.viewports = Some(vec![Viewport {
+            origin: [
+                screen.origin[0] + top_left[0] * scale[0],
+                screen.origin[1] + top_left[1] * scale[1],
+            ],
+            dimensions: [size[0] * scale[0], size[1] * scale[1]],
+            depth_range: screen.depth_range.clone(),
+        }]);
+        self.viewport_region = Some(ViewportRegion { screen, screen_units, size });
+        Ok(())
+    }
+
+    /// Goes back to drawing across the whole screen.
+    pub fn reset_viewport(&mut self) {
+        if let Some(region) = self.viewport_region.take() {
+            self.dynamic_state.viewports = Some(vec![region.screen]);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::sync::Arc;
+
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::framebuffer::FramebufferAbstract;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::image::{ImageAccess, SwapchainImage};
+use vulkano::swapchain::{
+    AcquireError, FullscreenExclusive, PresentMode, Surface, SurfaceTransform, Swapchain,
+    SwapchainAcquireFuture, SwapchainCreationError,
+};
+use vulkano::sync::{self, FlushError, GpuFuture};
+use vulkano_win::VkSurfaceBuild;
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::WindowId;
+
+use crate::config::MagmaConfig;
+use crate::core::{Color, MamgaGfx, Renderer};
+use crate::error::MagmaError;
+use crate::input::Input;
+use crate::{clear_values, window_builder, window_size_dependent_setup};
+
+/// The swapchain side of a window: everything the main window and the ones
+/// opened with `MamgaGfx::create_window` need to acquire an image, draw into
+/// it and present it.
+pub(crate) struct WindowOutput {
+    pub(crate) surface: Arc<Surface<winit::window::Window>>,
+    pub(crate) swapchain: Arc<Swapchain<winit::window::Window>>,
+    pub(crate) images: Vec<Arc<SwapchainImage<winit::window::Window>>>,
+    /// Indexed by frame in flight, then by swapchain image.
+    pub(crate) framebuffers: Vec<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
+    pub(crate) recreate_swapchain: bool,
+    /// Image acquired by `acquire`, waiting for `present`.
+    acquired: Option<(usize, SwapchainAcquireFuture<winit::window::Window>)>,
+}
+
+impl WindowOutput {
+    pub(crate) fn new(
+        surface: Arc<Surface<winit::window::Window>>,
+        swapchain: Arc<Swapchain<winit::window::Window>>,
+        images: Vec<Arc<SwapchainImage<winit::window::Window>>>,
+        framebuffers: Vec<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>>,
+    ) -> Self {
+        WindowOutput {
+            surface,
+            swapchain,
+            images,
+            framebuffers,
+            recreate_swapchain: false,
+            acquired: None,
+        }
+    }
+
+    pub(crate) fn handle_event(&mut self, renderer: &mut Renderer, event: &WindowEvent) {
+        match event {
+            WindowEvent::Resized(_) => {
+                self.recreate_swapchain = true;
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                renderer.scale_factor = *scale_factor;
+                self.recreate_swapchain = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Recreates the swapchain if it's out of date and acquires the next
+    /// image. Returns `None` when there is nothing to draw into, e.g. while
+    /// the window is minimised.
+    pub(crate) fn acquire(
+        &mut self,
+        renderer: &mut Renderer,
+        input: &mut Input,
+        config: &MagmaConfig,
+    ) -> Result<Option<usize>, MagmaError> {
+        if self.recreate_swapchain {
+            let dimensions: [u32; 2] = self.surface.window().inner_size().into();
+            let (new_swapchain, new_images) = match self.swapchain.recreate_with_dimensions(dimensions) {
+                Ok(r) => r,
+                Err(SwapchainCreationError::UnsupportedDimensions) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            self.swapchain = new_swapchain;
+            self.framebuffers = window_size_dependent_setup(
+                renderer.device.clone(),
+                &new_images,
+                renderer.render_pass.clone(),
+                &mut renderer.dynamic_state,
+                config.depth_buffer,
+                config.samples,
+                renderer.frames_in_flight(),
+                renderer.virtual_resolution.as_ref(),
+            )?;
+            input.set_virtual_placement(
+                renderer.virtual_resolution.map(|resolution| resolution.placement(new_images[0].dimensions())),
+            );
+            renderer.framebuffer_size = new_images[0].dimensions();
+            self.images = new_images;
+            self.recreate_swapchain = false;
+        }
+
+        let (image_num, suboptimal, acquire_future) =
+            match vulkano::swapchain::acquire_next_image(self.swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+        self.acquired = Some((image_num, acquire_future));
+        Ok(Some(image_num))
+    }
+
+    /// Framebuffer drawing into swapchain image `image_num` for frame in
+    /// flight `frame`.
+    pub(crate) fn framebuffer(&self, frame: usize, image_num: usize) -> Arc<dyn FramebufferAbstract + Send + Sync> {
+        self.framebuffers[frame][image_num].clone()
+    }
+
+    /// The image handed out by the last `acquire`.
+    pub(crate) fn acquired_image(&self) -> Result<usize, MagmaError> {
+        self.acquired.as_ref().map(|(image_num, _)| *image_num).ok_or_else(|| {
+            MagmaError::Swapchain("end_frame called without a successful begin_frame".to_string())
+        })
+    }
+
+    /// Submits `builder`, after the frame's render target passes, and
+    /// presents the acquired image. Returns whether the frame was actually
+    /// submitted, which it isn't when the swapchain turned out to be stale.
+    /// Any other flush failure is returned, after moving on to the next frame.
+    pub(crate) fn present(&mut self, renderer: &mut Renderer, builder: AutoCommandBufferBuilder) -> Result<bool, MagmaError> {
+        let (image_num, acquire_future) = self.acquired.take().ok_or_else(|| {
+            MagmaError::Swapchain("end_frame called without a successful begin_frame".to_string())
+        })?;
+        let command_buffer = builder.build()?;
+        let device = renderer.device.clone();
+        let queue = renderer.queue.clone();
+        let future = renderer.execute_target_passes(sync::now(device).boxed())?;
+        let future = renderer.execute_frame(Box::new(future.join(acquire_future)), command_buffer)?;
+        let future = Box::new(future.then_swapchain_present(queue, self.swapchain.clone(), image_num))
+            as Box<dyn GpuFuture>;
+
+        let current_frame = renderer.current_frame;
+        let submitted = match future.then_signal_fence_and_flush() {
+            Ok(future) => {
+                renderer.frames[current_frame].fence = Some(future);
+                renderer.frame_submitted();
+                Ok(true)
+            }
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                Ok(false)
+            }
+            Err(e) => Err(MagmaError::from(e)),
+        };
+        renderer.advance_frame()?;
+        submitted
+    }
+}
+
+impl Renderer {
+    /// Starts recording a frame into `framebuffer`, cleared to `clear_color`.
+    pub(crate) fn begin_frame_pass(
+        &mut self,
+        framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+        clear_color: [f32; 4],
+        config: &MagmaConfig,
+    ) -> Result<(), MagmaError> {
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())?;
+        builder.begin_render_pass(
+            framebuffer,
+            false,
+            clear_values(self.framebuffer_clear_color(clear_color), config.depth_buffer, config.samples),
+        )?;
+        self.command_buffer_builder = Some(builder);
+        self.clear_virtual_screen(clear_color)
+    }
+
+    /// Ends the frame's render pass and hands back its builder, for
+    /// anything recorded outside it.
+    pub(crate) fn end_frame_pass(&mut self) -> Result<AutoCommandBufferBuilder, MagmaError> {
+        self.end_targets()?;
+        let mut builder = self.take_command_buffer_builder()?;
+        builder.end_render_pass()?;
+        Ok(builder)
+    }
+
+    /// Stores a newly built pipeline and flags it for `share_pipelines`.
+    pub(crate) fn insert_pipeline(&mut self, name: String, pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>) {
+        self.pipelines.insert(name, pipeline);
+        self.pipelines_added = true;
+    }
+}
+
+/// A window opened with `MamgaGfx::create_window` after the main one.
+///
+/// It shares the main window's device, queue, render pass and pipelines, and
+/// has its own swapchain, framebuffers, input and `Renderer`, which
+/// `App::draw_window` draws into.
+pub struct WindowContext {
+    pub id: WindowId,
+    pub clear_color: [f32; 4],
+    pub renderer: Renderer,
+    pub input: Input,
+    pub(crate) output: WindowOutput,
+}
+
+impl WindowContext {
+    pub fn clear(&mut self, color: Color) {
+        self.clear_color = color.normalise();
+    }
+
+    pub fn surface(&self) -> &Arc<Surface<winit::window::Window>> {
+        &self.output.surface
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent) {
+        self.input.handle_event(event);
+        self.output.handle_event(&mut self.renderer, event);
+    }
+
+    /// Acquires the next swapchain image and starts recording into
+    /// `self.renderer`. Returns `false` when there is nothing to draw into.
+    pub(crate) fn begin_frame(&mut self, config: &MagmaConfig) -> Result<bool, MagmaError> {
+        self.renderer.poll_frames();
+        let image_num = match self.output.acquire(&mut self.renderer, &mut self.input, config)? {
+            Some(image_num) => image_num,
+            None => return Ok(false),
+        };
+        let framebuffer = self.output.framebuffer(self.renderer.current_frame, image_num);
+        self.renderer.begin_frame_pass(framebuffer, self.clear_color, config)?;
+        Ok(true)
+    }
+
+    /// Submits everything drawn since `begin_frame` and presents it.
+    pub(crate) fn end_frame(&mut self) -> Result<(), MagmaError> {
+        self.input.end_frame();
+        let builder = self.renderer.end_frame_pass()?;
+        self.output.present(&mut self.renderer, builder)?;
+        Ok(())
+    }
+
+    /// Blocks until the GPU is done with everything this window submitted.
+    fn wait_idle(&mut self) -> Result<(), MagmaError> {
+        for frame in self.renderer.frames.iter_mut() {
+            frame.wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// Gives `main` and every window each other's pipelines, so a pipeline only
+/// has to be built once for every window to use it. Does nothing unless one
+/// of them built a pipeline since the last call.
+pub(crate) fn share_pipelines(main: &mut Renderer, windows: &mut [WindowContext]) {
+    if !main.pipelines_added && windows.iter().all(|window| !window.renderer.pipelines_added) {
+        return;
+    }
+    for window in windows.iter_mut() {
+        for (name, pipeline) in window.renderer.pipelines.iter() {
+            if !main.pipelines.contains_key(name) {
+                main.pipelines.insert(name.clone(), pipeline.clone());
+            }
+        }
+    }
+    for window in windows.iter_mut() {
+        for (name, pipeline) in main.pipelines.iter() {
+            if !window.renderer.pipelines.contains_key(name) {
+                window.renderer.pipelines.insert(name.clone(), pipeline.clone());
+            }
+        }
+        window.renderer.pipelines_added = false;
+    }
+    main.pipelines_added = false;
+}
+
+impl MamgaGfx {
+    /// Opens another window drawing with this device.
+    ///
+    /// Only the window settings of `config` (title, size, resizability and
+    /// window mode) are used. Everything else follows the main window, so
+    /// pipelines stay compatible between the two. `target` is the event loop:
+    /// `&window.event_loop` before calling `run_app`, or the one passed to
+    /// `App::manage_windows` while it runs. The window mode actually used is
+    /// written back into `config`.
+    pub fn create_window<T: 'static>(
+        &mut self,
+        target: &EventLoopWindowTarget<T>,
+        config: &mut MagmaConfig,
+    ) -> Result<WindowId, MagmaError> {
+        let surface = window_builder(target, config).build_vk_surface(target, self.instance.clone())?;
+        if !surface.is_supported(self.queue.family())? {
+            return Err(MagmaError::Surface("the window can't be presented to from this device's queue".to_string()));
+        }
+
+        let caps = surface.capabilities(self.device.physical_device())?;
+        let format = self.output.swapchain.format();
+        let &(_, color_space) = caps
+            .supported_formats
+            .iter()
+            .find(|&&(f, _)| f == format)
+            .ok_or_else(|| MagmaError::Surface(format!("the window doesn't support {:?}", format)))?;
+        let alpha = caps.supported_composite_alpha.iter().next()
+            .ok_or_else(|| MagmaError::Surface("no supported composite alpha mode".to_string()))?;
+        let present_mode = if caps.present_modes.supports(self.output.swapchain.present_mode()) {
+            self.output.swapchain.present_mode()
+        } else {
+            PresentMode::Fifo
+        };
+        let dimensions: [u32; 2] = surface.window().inner_size().into();
+
+        let (swapchain, images) = Swapchain::new(
+            self.device.clone(),
+            surface.clone(),
+            caps.min_image_count,
+            format,
+            dimensions,
+            1,
+            caps.supported_usage_flags,
+            &self.queue,
+            SurfaceTransform::Identity,
+            alpha,
+            present_mode,
+            FullscreenExclusive::Default,
+            true,
+            color_space,
+        )?;
+
+        let mut dynamic_state = DynamicState::none();
+        let framebuffers = window_size_dependent_setup(
+            self.device.clone(),
+            &images,
+            self.renderer.render_pass.clone(),
+            &mut dynamic_state,
+            self.config.depth_buffer,
+            self.config.samples,
+            self.config.frames_in_flight,
+            self.renderer.virtual_resolution.as_ref(),
+        )?;
+
+        let scale_factor = surface.window().scale_factor();
+        let mut renderer = Renderer::new(
+            self.device.clone(),
+            self.queue.clone(),
+            self.renderer.render_pass.clone(),
+            dynamic_state,
+            self.config.frames_in_flight,
+            self.renderer.pipeline_cache.clone(),
+        );
+        renderer.pipelines = self.renderer.pipelines.clone();
+        renderer.window_id = Some(surface.window().id());
+        renderer.framebuffer_size = images[0].dimensions();
+        renderer.coordinate_mode = self.renderer.coordinate_mode;
+        renderer.scale_factor = scale_factor;
+        renderer.virtual_resolution = self.renderer.virtual_resolution;
+        renderer.output_srgb = self.renderer.output_srgb;
+        let virtual_placement = renderer.virtual_resolution.map(|resolution| resolution.placement(images[0].dimensions()));
+
+        let id = surface.window().id();
+        self.windows.push(WindowContext {
+            id,
+            clear_color: self.clear_color,
+            renderer,
+            input: Input::new()
+                .with_scale_factor(scale_factor)
+                .with_virtual_placement(virtual_placement),
+            output: WindowOutput::new(surface, swapchain, images, framebuffers),
+        });
+        Ok(id)
+    }
+
+    /// Closes a window opened with `create_window`, once the GPU is done with
+    /// it. Returns `false` if there is no such window.
+    pub fn close_window(&mut self, id: WindowId) -> Result<bool, MagmaError> {
+        match self.windows.iter().position(|window| window.id == id) {
+            Some(index) => {
+                let mut window = self.windows.remove(index);
+                window.wait_idle()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn window(&mut self, id: WindowId) -> Option<&mut WindowContext> {
+        self.windows.iter_mut().find(|window| window.id == id)
+    }
+
+    /// Every window opened with `create_window` that is still open.
+    pub fn windows(&mut self) -> &mut [WindowContext] {
+        &mut self.windows
+    }
+}